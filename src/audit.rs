@@ -0,0 +1,507 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use serde_json::{Value, json};
+
+use crate::diff::quote_ident;
+use crate::lint::{self, Finding, Rule, Severity};
+use crate::model::{Function, signature_types};
+use crate::structs::{ObjectType, Schema, SchemaSection};
+
+// and definer-without-search-path, which audit shares with lint
+pub const RULES: [Rule; 5] = [
+    Rule {
+        id: "definer-executable-by-public",
+        severity: Severity::Error,
+        description: "a SECURITY DEFINER function anyone can execute",
+    },
+    Rule {
+        id: "definer-executable-by-api-role",
+        severity: Severity::Warning,
+        description: "a SECURITY DEFINER function an API role can execute",
+    },
+    Rule {
+        id: "untrusted-language",
+        severity: Severity::Warning,
+        description: "a function in a language that can reach the server's files and processes",
+    },
+    Rule {
+        id: "grant-to-public",
+        severity: Severity::Warning,
+        description: "a privilege granted to PUBLIC, now or by default on new objects",
+    },
+    Rule {
+        id: "unparsed-function",
+        severity: Severity::Error,
+        description: "a function the model can't read, audited from its attributes only",
+    },
+];
+
+// the languages only a superuser can create functions in, because they aren't sandboxed
+const UNTRUSTED_LANGUAGES: [&str; 7] = [
+    "c",
+    "plperlu",
+    "plpython2u",
+    "plpython3u",
+    "plpythonu",
+    "plsh",
+    "pltclu",
+];
+
+// GRANT ALL ON FUNCTION shop.total(a integer) TO api;
+// REVOKE ALL ON FUNCTION shop.total(a integer) FROM PUBLIC;
+struct Grant<'a> {
+    revoke: bool,
+    privileges: &'a str,
+    grantee: &'a str,
+}
+
+// the privilege escalation risks of a schema, by the schema they are in
+pub struct Report {
+    schemas: BTreeMap<String, Vec<Finding>>,
+}
+
+impl Report {
+    // `api_roles` are the roles clients connect as, e.g. the anon and authenticated roles of
+    // PostgREST
+    pub fn new(schema: &Schema, api_roles: &[String]) -> Self {
+        // the grants on each function, by the schema, name and argument types it is known by in
+        // the header of the function
+        let mut function_grants: HashMap<(&str, &str, String), Vec<Grant>> = HashMap::new();
+        let mut report = Report {
+            schemas: BTreeMap::new(),
+        };
+        for section in schema.sections() {
+            let header = &section.header;
+            if !matches!(header.object_type, ObjectType::Acl | ObjectType::DefaultAcl) {
+                continue;
+            }
+            let grants = grants(section);
+            for grant in grants.iter().filter(|g| !g.revoke && g.grantee == "PUBLIC") {
+                let (object, schema_name) = acl_object(section);
+                report.push(
+                    schema_name,
+                    "grant-to-public",
+                    object,
+                    format!("{} granted to PUBLIC", grant.privileges),
+                );
+            }
+            if let Some(("FUNCTION" | "PROCEDURE", name)) = header.target() {
+                let types = header.signature.as_deref().and_then(signature_types);
+                if let Some(types) = types {
+                    function_grants
+                        .entry((header.schema.as_str(), name, types))
+                        .or_default()
+                        .extend(grants);
+                }
+            }
+        }
+
+        for section in schema.sections() {
+            let header = &section.header;
+            if !matches!(
+                header.object_type,
+                ObjectType::Function | ObjectType::Procedure
+            ) {
+                continue;
+            }
+            let object = format!(
+                "{}.{}{}",
+                header.schema,
+                header.name,
+                header.signature.as_deref().unwrap_or("")
+            );
+            let (language, security_definer, search_path) = match Function::parse(section) {
+                Ok(function) => (
+                    function.language,
+                    function.security_definer,
+                    function
+                        .config
+                        .iter()
+                        .any(|(name, _)| name == "search_path"),
+                ),
+                Err(e) => {
+                    report.push(
+                        &header.schema,
+                        "unparsed-function",
+                        object.clone(),
+                        format!("{e}, audited from its attributes only"),
+                    );
+                    attributes(section)
+                }
+            };
+            if UNTRUSTED_LANGUAGES.contains(&language.as_str()) {
+                report.push(
+                    &header.schema,
+                    "untrusted-language",
+                    object.clone(),
+                    format!("written in {language}"),
+                );
+            }
+            if !security_definer {
+                continue;
+            }
+            if !search_path {
+                report.add(
+                    &header.schema,
+                    lint::definer_without_search_path(object.clone()),
+                );
+            }
+            let key = (
+                header.schema.as_str(),
+                header.name.as_str(),
+                header.signature.clone().unwrap_or_else(|| "()".to_string()),
+            );
+            let grants = function_grants.get(&key).map_or(&[][..], Vec::as_slice);
+            if executes(grants, "PUBLIC") {
+                report.push(
+                    &header.schema,
+                    "definer-executable-by-public",
+                    object,
+                    format!(
+                        "runs as its owner {} and PUBLIC can execute it",
+                        header.owner
+                    ),
+                );
+                continue;
+            }
+            let roles: Vec<&str> = api_roles
+                .iter()
+                .map(String::as_str)
+                .filter(|role| executes(grants, &quote_ident(role)))
+                .collect();
+            if !roles.is_empty() {
+                report.push(
+                    &header.schema,
+                    "definer-executable-by-api-role",
+                    object,
+                    format!(
+                        "runs as its owner {} and {} can execute it",
+                        header.owner,
+                        roles.join(", ")
+                    ),
+                );
+            }
+        }
+        for findings in report.schemas.values_mut() {
+            lint::sort(findings);
+        }
+        report
+    }
+
+    fn push(&mut self, schema: &str, id: &str, object: String, message: String) {
+        let rule = RULES
+            .iter()
+            .find(|rule| rule.id == id)
+            .expect("a rule of RULES");
+        self.add(schema, Finding::new(rule, object, message));
+    }
+
+    fn add(&mut self, schema: &str, finding: Finding) {
+        self.schemas
+            .entry(schema.to_string())
+            .or_default()
+            .push(finding);
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        lint::count(self.schemas.values().flatten(), severity)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let schemas: serde_json::Map<String, Value> = self
+            .schemas
+            .iter()
+            .map(|(schema, findings)| {
+                let findings: Vec<Value> = findings.iter().map(Finding::to_json).collect();
+                (schema.clone(), json!(findings))
+            })
+            .collect();
+        json!({ "schemas": schemas })
+    }
+}
+
+// admin
+//   error[definer-executable-by-public] admin.reset(bigint): runs as its owner postgres and ...
+//
+// findings: 1 error, 0 warning, 0 info
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.schemas.is_empty() {
+            return write!(f, "no findings");
+        }
+        for (schema, findings) in &self.schemas {
+            writeln!(f, "{schema}")?;
+            for finding in findings {
+                writeln!(f, "  {finding}")?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "{}", lint::summary(self.schemas.values().flatten()))
+    }
+}
+
+// the language, SECURITY DEFINER and SET search_path of a function the model can't read, from
+// the words of its statement before the body, however they are laid out
+//
+// CREATE FUNCTION shop.total(a integer) RETURNS integer
+//     LANGUAGE sql SECURITY DEFINER
+//     SET search_path TO 'shop'
+//     RETURN (a + 1);
+fn attributes(section: &SchemaSection) -> (String, bool, bool) {
+    let mut language = None;
+    let mut security = None;
+    let mut search_path = false;
+    let mut body = false;
+    let mut words = section.body.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "AS" | "RETURN" | "BEGIN" => {
+                body = true;
+                break;
+            }
+            "LANGUAGE" => language = words.next(),
+            "SECURITY" => security = words.next(),
+            "SET" => search_path |= words.next() == Some("search_path"),
+            _ => (),
+        }
+    }
+    // SECURITY INVOKER is the default pg_dump leaves out, but only once we know we read every
+    // attribute. Otherwise the function counts as a definer, so nothing goes unreported
+    let security_definer = match security {
+        Some(mode) => mode != "INVOKER",
+        None => !body || language.is_none(),
+    };
+    let language = language.unwrap_or("").trim_matches(['\'', ';']).to_string();
+    (language, security_definer, search_path)
+}
+
+// the GRANT and REVOKE statements of an ACL or DEFAULT ACL section
+fn grants(section: &SchemaSection) -> Vec<Grant<'_>> {
+    let mut grants = Vec::new();
+    for line in section.body.lines() {
+        // ALTER DEFAULT PRIVILEGES FOR ROLE postgres IN SCHEMA api GRANT ALL ON FUNCTIONS TO api;
+        let statement = match line.find("GRANT ").or_else(|| line.find("REVOKE ")) {
+            Some(start) => &line[start..],
+            None => continue,
+        };
+        let statement = statement.trim_end_matches(';');
+        let statement = statement
+            .strip_suffix(" WITH GRANT OPTION")
+            .unwrap_or(statement);
+        let (revoke, rest, to) = match statement.strip_prefix("REVOKE ") {
+            Some(rest) => (true, rest, " FROM "),
+            None => (false, &statement["GRANT ".len()..], " TO "),
+        };
+        let (Some((privileges, _)), Some((_, grantee))) =
+            (rest.split_once(" ON "), rest.rsplit_once(to))
+        else {
+            continue;
+        };
+        grants.push(Grant {
+            revoke,
+            privileges,
+            grantee,
+        });
+    }
+    grants
+}
+
+// whether a role can execute a function with these grants, PUBLIC can unless they revoke it
+fn executes(grants: &[Grant], role: &str) -> bool {
+    grants
+        .iter()
+        .filter(|grant| grant.grantee == role)
+        .filter(|grant| grant.privileges == "ALL" || grant.privileges.contains("EXECUTE"))
+        .fold(role == "PUBLIC", |_, grant| !grant.revoke)
+}
+
+// what an ACL section grants on and the schema it belongs to, `SCHEMA api` to the api schema
+fn acl_object(section: &SchemaSection) -> (String, &str) {
+    let header = &section.header;
+    let signature = header.signature.as_deref().unwrap_or("");
+    match (header.object_type, header.target()) {
+        // DEFAULT PRIVILEGES FOR TABLES
+        (ObjectType::DefaultAcl, _) => (header.name.clone(), &header.schema),
+        (_, Some(("SCHEMA", name))) => (format!("SCHEMA {name}"), name),
+        (_, Some((kind, name))) => (
+            format!("{kind} {}.{name}{signature}", header.schema),
+            &header.schema,
+        ),
+        (_, None) => (header.name.clone(), &header.schema),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant<'a>(revoke: bool, privileges: &'a str, grantee: &'a str) -> Grant<'a> {
+        Grant {
+            revoke,
+            privileges,
+            grantee,
+        }
+    }
+
+    #[test]
+    fn public_executes_until_revoked() {
+        assert!(executes(&[], "PUBLIC"));
+        assert!(!executes(&[], "api"));
+        let revoked = [grant(true, "ALL", "PUBLIC")];
+        assert!(!executes(&revoked, "PUBLIC"));
+        let granted_again = [
+            grant(true, "ALL", "PUBLIC"),
+            grant(false, "EXECUTE", "PUBLIC"),
+        ];
+        assert!(executes(&granted_again, "PUBLIC"));
+    }
+
+    #[test]
+    fn roles_execute_what_they_were_last_granted() {
+        assert!(executes(&[grant(false, "ALL", "api")], "api"));
+        assert!(!executes(&[grant(false, "ALL", "api")], "web"));
+        let revoked = [grant(false, "ALL", "api"), grant(true, "EXECUTE", "api")];
+        assert!(!executes(&revoked, "api"));
+        // other privileges don't matter to a function
+        assert!(!executes(&[grant(false, "SELECT", "api")], "api"));
+    }
+
+    #[test]
+    fn grants_are_read_from_acl_sections() {
+        let schema = Schema::from_sections(&[
+            (
+                "FUNCTION reset(p_id bigint)",
+                "ACL",
+                "REVOKE ALL ON FUNCTION shop.reset(p_id bigint) FROM PUBLIC;\nGRANT ALL ON \
+                 FUNCTION shop.reset(p_id bigint) TO \"Api\" WITH GRANT OPTION;",
+            ),
+            (
+                "DEFAULT PRIVILEGES FOR FUNCTIONS",
+                "DEFAULT ACL",
+                "ALTER DEFAULT PRIVILEGES FOR ROLE postgres IN SCHEMA shop GRANT ALL ON \
+                 FUNCTIONS TO PUBLIC;",
+            ),
+        ]);
+        let grants: Vec<(bool, &str, &str)> = schema
+            .sections()
+            .flat_map(grants)
+            .map(|g| (g.revoke, g.privileges, g.grantee))
+            .collect();
+        assert_eq!(
+            grants,
+            [
+                (true, "ALL", "PUBLIC"),
+                (false, "ALL", "\"Api\""),
+                (false, "ALL", "PUBLIC"),
+            ]
+        );
+    }
+
+    #[test]
+    fn definer_functions_are_checked_against_their_grants() {
+        let schema = Schema::from_sections(&[
+            (
+                "reset(bigint)",
+                "FUNCTION",
+                "CREATE FUNCTION shop.reset(p_id bigint) RETURNS void\n    LANGUAGE sql \
+                 SECURITY DEFINER\n    SET search_path TO 'shop'\n    RETURN NULL::void;",
+            ),
+            (
+                "FUNCTION reset(p_id bigint)",
+                "ACL",
+                "REVOKE ALL ON FUNCTION shop.reset(p_id bigint) FROM PUBLIC;\nGRANT ALL ON \
+                 FUNCTION shop.reset(p_id bigint) TO \"Api\";",
+            ),
+            (
+                "total(integer)",
+                "FUNCTION",
+                "CREATE FUNCTION shop.total(a integer) RETURNS integer\n    LANGUAGE plpython3u \
+                 SECURITY DEFINER\n    AS $$ return a $$;",
+            ),
+        ]);
+        let report = Report::new(&schema, &["Api".to_string()]);
+        assert_eq!(
+            report.to_string(),
+            "shop\n  warning[definer-executable-by-api-role] shop.reset(bigint): runs as its \
+             owner postgres and Api can execute it\n  error[definer-executable-by-public] \
+             shop.total(integer): runs as its owner postgres and PUBLIC can execute it\n  \
+             error[definer-without-search-path] shop.total(integer): runs as its owner with the \
+             caller's search_path\n  warning[untrusted-language] shop.total(integer): written in \
+             plpython3u\n\nfindings: 2 error, 2 warning, 0 info"
+        );
+        assert!(
+            Report::new(&schema, &[]).to_json()["schemas"]["shop"][0]["rule"]
+                .as_str()
+                .is_some_and(|rule| rule == "definer-executable-by-public")
+        );
+    }
+
+    #[test]
+    fn functions_the_model_cant_read_are_audited_from_their_attributes() {
+        let schema = Schema::from_sections(&[(
+            "sd(integer)",
+            "FUNCTION",
+            "CREATE FUNCTION shop.sd(a integer) RETURNS integer\n    LANGUAGE c SECURITY \
+             DEFINER WEIRD\n    AS 'sd', 'sd';",
+        )]);
+        assert_eq!(
+            attributes(schema.sections().next().unwrap()),
+            ("c".to_string(), true, false)
+        );
+        let report = Report::new(&schema, &[]);
+        assert_eq!(
+            report.to_string(),
+            "shop\n  error[definer-executable-by-public] shop.sd(integer): runs as its owner \
+             postgres and PUBLIC can execute it\n  error[definer-without-search-path] \
+             shop.sd(integer): runs as its owner with the caller's search_path\n  \
+             error[unparsed-function] shop.sd(integer): unsupported function attribute WEIRD, \
+             audited from its attributes only\n  warning[untrusted-language] \
+             shop.sd(integer): written in c\n\nfindings: 3 error, 1 warning, 0 info"
+        );
+        assert_eq!(report.count(Severity::Error), 3);
+    }
+
+    #[test]
+    fn attributes_are_read_in_any_layout() {
+        let schema = Schema::from_sections(&[
+            (
+                "trg()",
+                "FUNCTION",
+                "CREATE FUNCTION shop.trg()\n RETURNS trigger\n LANGUAGE plpgsql\n SECURITY \
+                 DEFINER\n SET search_path TO 'shop'\nAS $function$ BEGIN RETURN NEW; END \
+                 $function$;",
+            ),
+            (
+                "total(integer)",
+                "FUNCTION",
+                "CREATE FUNCTION shop.total(a integer) RETURNS integer LANGUAGE sql STABLE \
+                 RETURN (a + 1);",
+            ),
+        ]);
+        let attributes: Vec<_> = schema.sections().map(attributes).collect();
+        assert_eq!(
+            attributes,
+            [
+                ("plpgsql".to_string(), true, true),
+                ("sql".to_string(), false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn attributes_fail_closed() {
+        // no body, so there may be a SECURITY DEFINER we didn't get to
+        let schema = Schema::from_sections(&[(
+            "total(integer)",
+            "FUNCTION",
+            "CREATE FUNCTION shop.total(a integer) RETURNS integer\n    LANGUAGE sql;",
+        )]);
+        assert_eq!(
+            attributes(schema.sections().next().unwrap()),
+            ("sql".to_string(), true, false)
+        );
+    }
+}
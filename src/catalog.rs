@@ -0,0 +1,1835 @@
+use std::{collections::HashMap, error::Error};
+
+use postgres::{Client, NoTls};
+
+use crate::diff::{quote_ident, quote_literal};
+use crate::structs::{Diagnostic, DumpMetadata, ObjectType, Schema, SchemaHeader, SchemaSection};
+
+// everything outside the system schemas, the same objects pg_dump would dump
+const USER_NAMESPACE: &str = "n.nspname NOT IN ('pg_catalog', 'information_schema')
+    AND n.nspname NOT LIKE 'pg\\_toast%'
+    AND n.nspname NOT LIKE 'pg\\_temp%'";
+
+// objects created by an extension are recreated by CREATE EXTENSION, pg_dump skips them too
+fn not_extension_member(catalog: &str, oid: &str) -> String {
+    format!(
+        "NOT EXISTS (SELECT 1 FROM pg_depend dep WHERE dep.classid = '{catalog}'::regclass
+            AND dep.objid = {oid} AND dep.deptype = 'e')"
+    )
+}
+
+// builds the same model a pg_dump would parse into by reading pg_catalog directly, for when a
+// pg_dump matching the server version isn't available. Statements are written the way pg_dump
+// writes them so the rest of the tool can't tell the two backends apart
+pub fn get_schema(db_url: &str) -> Result<Schema, Box<dyn Error>> {
+    read_schema(db_url).map_err(|e| match e.as_db_error() {
+        Some(db_error) => format!("catalog query failed: {}", db_error.message()).into(),
+        None => e.into(),
+    })
+}
+
+fn read_schema(db_url: &str) -> Result<Schema, postgres::Error> {
+    let mut client = Client::connect(db_url, NoTls)?;
+    // with an empty search_path every name the server prints back is schema qualified
+    client.batch_execute("SELECT pg_catalog.set_config('search_path', '', false)")?;
+
+    let mut schema = Schema::default();
+    // there is no pg_dump, so no client version, and the session is set up like pg_dump does it
+    let server_version: String = client.query_one("SHOW server_version", &[])?.get(0);
+    schema.set_metadata(DumpMetadata {
+        server_version: server_version.parse().ok(),
+        search_path: Some(String::new()),
+        ..DumpMetadata::default()
+    });
+    read_schemas(&mut client, &mut schema)?;
+    read_extensions(&mut client, &mut schema)?;
+    read_types(&mut client, &mut schema)?;
+    read_functions(&mut client, &mut schema)?;
+    read_tables(&mut client, &mut schema)?;
+    read_sequences(&mut client, &mut schema)?;
+    read_views(&mut client, &mut schema)?;
+    read_constraints(&mut client, &mut schema)?;
+    read_indexes(&mut client, &mut schema)?;
+    read_statistics(&mut client, &mut schema)?;
+    read_triggers(&mut client, &mut schema)?;
+    read_event_triggers(&mut client, &mut schema)?;
+    read_rules(&mut client, &mut schema)?;
+    read_policies(&mut client, &mut schema)?;
+    read_comments(&mut client, &mut schema)?;
+    read_acls(&mut client, &mut schema)?;
+    read_default_acls(&mut client, &mut schema)?;
+    report_unsupported(&mut client, &mut schema)?;
+    Ok(schema)
+}
+
+fn push(
+    schema: &mut Schema,
+    object_type: ObjectType,
+    namespace: &str,
+    name: &str,
+    signature: Option<String>,
+    owner: &str,
+    statements: &[String],
+) {
+    let header = SchemaHeader::new(object_type, namespace, name, signature, owner);
+    let body = format!("\n{}\n", statements.join("\n\n\n"));
+    schema.push(SchemaSection::new(header, body));
+}
+
+fn read_schemas(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, quote_ident(n.nspname), pg_get_userbyid(n.nspowner)::text
+        FROM pg_namespace n
+        WHERE {USER_NAMESPACE} AND n.nspname <> 'public' AND {}
+        ORDER BY n.nspname",
+        not_extension_member("pg_namespace", "n.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (name, quoted, owner): (String, String, String) = (row.get(0), row.get(1), row.get(2));
+        push(
+            schema,
+            ObjectType::Schema,
+            "-",
+            &name,
+            None,
+            &owner,
+            &[
+                format!("CREATE SCHEMA {quoted};"),
+                format!("ALTER SCHEMA {quoted} OWNER TO {};", quote_ident(&owner)),
+            ],
+        );
+    }
+    Ok(())
+}
+
+fn read_extensions(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = "SELECT e.extname::text, quote_ident(e.extname), quote_ident(n.nspname)
+        FROM pg_extension e JOIN pg_namespace n ON n.oid = e.extnamespace
+        WHERE e.extname <> 'plpgsql'
+        ORDER BY e.extname";
+    for row in client.query(query, &[])? {
+        let (name, quoted, namespace): (String, String, String) =
+            (row.get(0), row.get(1), row.get(2));
+        push(
+            schema,
+            ObjectType::Extension,
+            "-",
+            &name,
+            None,
+            "-",
+            &[format!(
+                "CREATE EXTENSION IF NOT EXISTS {quoted} WITH SCHEMA {namespace};"
+            )],
+        );
+    }
+    Ok(())
+}
+
+fn read_types(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, t.typname::text,
+            quote_ident(n.nspname) || '.' || quote_ident(t.typname),
+            pg_get_userbyid(t.typowner)::text, t.typtype::text,
+            (SELECT string_agg(quote_literal(e.enumlabel), E',\\n    ' ORDER BY e.enumsortorder)
+                FROM pg_enum e WHERE e.enumtypid = t.oid),
+            (SELECT string_agg(quote_ident(a.attname) || ' ' || format_type(a.atttypid, a.atttypmod),
+                    E',\\n\\t' ORDER BY a.attnum)
+                FROM pg_attribute a
+                WHERE a.attrelid = t.typrelid AND a.attnum > 0 AND NOT a.attisdropped),
+            format_type(t.typbasetype, t.typtypmod), t.typnotnull, t.typdefault,
+            (SELECT string_agg(E'\\n\\tCONSTRAINT ' || quote_ident(c.conname) || ' '
+                    || pg_get_constraintdef(c.oid), '' ORDER BY c.conname)
+                FROM pg_constraint c WHERE c.contypid = t.oid AND c.contype = 'c')
+        FROM pg_type t JOIN pg_namespace n ON n.oid = t.typnamespace
+        WHERE {USER_NAMESPACE} AND {}
+            AND (t.typtype IN ('e', 'd')
+                OR (t.typtype = 'c'
+                    AND (SELECT c.relkind FROM pg_class c WHERE c.oid = t.typrelid) = 'c'))
+        ORDER BY n.nspname, t.typname",
+        not_extension_member("pg_type", "t.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, name, qualified, owner, kind): (String, String, String, String, String) =
+            (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4));
+        let (object_type, create) = match kind.as_str() {
+            "e" => {
+                let labels: Option<String> = row.get(5);
+                (
+                    ObjectType::Type,
+                    format!(
+                        "CREATE TYPE {qualified} AS ENUM (\n    {}\n);",
+                        labels.unwrap_or_default()
+                    ),
+                )
+            }
+            "c" => {
+                let attributes: Option<String> = row.get(6);
+                (
+                    ObjectType::Type,
+                    format!(
+                        "CREATE TYPE {qualified} AS (\n\t{}\n);",
+                        attributes.unwrap_or_default()
+                    ),
+                )
+            }
+            _ => {
+                let (base, not_null, default, checks): (
+                    String,
+                    bool,
+                    Option<String>,
+                    Option<String>,
+                ) = (row.get(7), row.get(8), row.get(9), row.get(10));
+                let mut create = format!("CREATE DOMAIN {qualified} AS {base}");
+                if not_null {
+                    create.push_str(" NOT NULL");
+                }
+                if let Some(default) = default {
+                    create.push_str(&format!(" DEFAULT {default}"));
+                }
+                create.push_str(&checks.unwrap_or_default());
+                create.push(';');
+                (ObjectType::Domain, create)
+            }
+        };
+        let alter = match object_type {
+            ObjectType::Domain => {
+                format!("ALTER DOMAIN {qualified} OWNER TO {};", quote_ident(&owner))
+            }
+            _ => format!("ALTER TYPE {qualified} OWNER TO {};", quote_ident(&owner)),
+        };
+        push(
+            schema,
+            object_type,
+            &namespace,
+            &name,
+            None,
+            &owner,
+            &[create, alter],
+        );
+    }
+    Ok(())
+}
+
+fn read_functions(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    // SQL standard bodies came with PostgreSQL 14
+    let version: i32 = client
+        .query_one("SELECT current_setting('server_version_num')::integer", &[])?
+        .get(0);
+    let sql_body = match version >= 140000 {
+        true => "CASE WHEN p.prosqlbody IS NOT NULL THEN pg_get_function_sqlbody(p.oid) END",
+        false => "NULL::text",
+    };
+    let query = format!(
+        "SELECT n.nspname::text, p.proname::text,
+            quote_ident(n.nspname) || '.' || quote_ident(p.proname),
+            oidvectortypes(p.proargtypes), pg_get_function_identity_arguments(p.oid),
+            p.prokind::text, pg_get_userbyid(p.proowner)::text,
+            pg_get_function_arguments(p.oid), pg_get_function_result(p.oid),
+            l.lanname::text, p.provolatile::text, p.proisstrict, p.prosecdef, p.proleakproof,
+            p.procost::text, p.prorows::text, p.proretset,
+            CASE WHEN p.prosupport <> 0 THEN p.prosupport::regproc::text END,
+            p.proparallel::text,
+            (SELECT string_agg('FOR TYPE ' || format_type(t, NULL), ', ')
+                FROM unnest(p.protrftypes) t),
+            p.prosrc, p.probin, {sql_body},
+            CASE WHEN p.prokind IN ('f', 'p') AND p.proconfig IS NOT NULL
+                THEN pg_get_functiondef(p.oid) END
+        FROM pg_proc p
+            JOIN pg_namespace n ON n.oid = p.pronamespace
+            JOIN pg_language l ON l.oid = p.prolang
+        WHERE {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, p.proname, 4",
+        not_extension_member("pg_proc", "p.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, name, qualified, arg_types, identity_args, kind, owner): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+            row.get(6),
+        );
+        let (object_type, keyword) = match kind.as_str() {
+            "p" => (ObjectType::Procedure, "PROCEDURE"),
+            "f" => (ObjectType::Function, "FUNCTION"),
+            _ => {
+                schema.report(Diagnostic {
+                    line: None,
+                    header: format!("{namespace}.{name}({arg_types})"),
+                    reason: "aggregates and window functions aren't read from the catalog"
+                        .to_string(),
+                });
+                continue;
+            }
+        };
+        let (arguments, result, language): (String, Option<String>, String) =
+            (row.get(7), row.get(8), row.get(9));
+        let (volatility, strict, security_definer, leakproof): (String, bool, bool, bool) =
+            (row.get(10), row.get(11), row.get(12), row.get(13));
+        let (cost, rows, returns_set, support, parallel): (
+            String,
+            String,
+            bool,
+            Option<String>,
+            String,
+        ) = (
+            row.get(14),
+            row.get(15),
+            row.get(16),
+            row.get(17),
+            row.get(18),
+        );
+        let (transforms, source, binary, sql_body, definition): (
+            Option<String>,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ) = (
+            row.get(19),
+            row.get(20),
+            row.get(21),
+            row.get(22),
+            row.get(23),
+        );
+
+        // the layout of pg_dump's dumpFunc
+        let mut create = format!("CREATE {keyword} {qualified}({arguments})");
+        if let Some(result) = result.filter(|_| object_type == ObjectType::Function) {
+            create.push_str(&format!(" RETURNS {result}"));
+        }
+        create.push_str(&format!("\n    LANGUAGE {}", quote_ident(&language)));
+        if let Some(transforms) = transforms {
+            create.push_str(&format!("\n    TRANSFORM {transforms}"));
+        }
+        match volatility.as_str() {
+            "i" => create.push_str(" IMMUTABLE"),
+            "s" => create.push_str(" STABLE"),
+            _ => (),
+        }
+        for (set, attribute) in [
+            (strict, " STRICT"),
+            (security_definer, " SECURITY DEFINER"),
+            (leakproof, " LEAKPROOF"),
+        ] {
+            if set {
+                create.push_str(attribute);
+            }
+        }
+        // the default cost is 1 for C and internal functions and 100 for the rest
+        let default_cost = match language.as_str() {
+            "c" | "internal" => "1",
+            _ => "100",
+        };
+        if cost != "0" && cost != default_cost {
+            create.push_str(&format!(" COST {cost}"));
+        }
+        if returns_set && rows != "0" && rows != "1000" {
+            create.push_str(&format!(" ROWS {rows}"));
+        }
+        if let Some(support) = support {
+            create.push_str(&format!(" SUPPORT {support}"));
+        }
+        match parallel.as_str() {
+            "s" => create.push_str(" PARALLEL SAFE"),
+            "r" => create.push_str(" PARALLEL RESTRICTED"),
+            _ => (),
+        }
+        // pg_get_functiondef quotes the settings the way pg_dump does, one per line before the
+        // body, which starts unindented
+        for line in definition.iter().flat_map(|d| d.lines().skip(1)) {
+            match line.strip_prefix(' ') {
+                Some(setting) if setting.starts_with("SET ") => {
+                    create.push_str(&format!("\n    {setting}"))
+                }
+                Some(_) => (),
+                None => break,
+            }
+        }
+        let body = match (sql_body, binary.filter(|b| !b.is_empty())) {
+            (Some(sql_body), _) => sql_body,
+            // C functions are the library and the symbol in it
+            (None, Some(binary)) if source.is_empty() => format!("AS {}", quote_literal(&binary)),
+            (None, Some(binary)) => {
+                let symbol = match source.contains(['\'', '\\']) {
+                    true => dollar_quote(&source),
+                    false => quote_literal(&source),
+                };
+                format!("AS {}, {symbol}", quote_literal(&binary))
+            }
+            (None, None) => format!("AS {}", dollar_quote(&source)),
+        };
+        create.push_str(&format!("\n    {body};"));
+        push(
+            schema,
+            object_type,
+            &namespace,
+            &name,
+            Some(format!("({arg_types})")),
+            &owner,
+            &[
+                create,
+                format!(
+                    "ALTER {keyword} {qualified}({identity_args}) OWNER TO {};",
+                    quote_ident(&owner)
+                ),
+            ],
+        );
+    }
+    Ok(())
+}
+
+// the dollar quotes pg_dump picks, $$ unless the string holds a $, then $_$, $_X$ and so on
+fn dollar_quote(s: &str) -> String {
+    const SUFFIXES: &[u8] = b"_XXXXXXX";
+    let mut tag = "$".to_string();
+    let mut next = 0;
+    while s.contains(&tag) {
+        tag.push(SUFFIXES[next] as char);
+        next = (next + 1) % SUFFIXES.len();
+    }
+    format!("{tag}${s}{tag}$")
+}
+
+struct Column {
+    definition: String,
+    // serial style defaults are dumped as a DEFAULT section after the sequence exists
+    separate_default: Option<(String, String, String)>,
+}
+
+fn read_tables(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let mut columns: HashMap<u32, Vec<Column>> = HashMap::new();
+    let query = "SELECT a.attrelid, a.attname::text, quote_ident(a.attname),
+            format_type(a.atttypid, a.atttypmod), a.attnotnull, a.attgenerated::text,
+            pg_get_expr(d.adbin, d.adrelid),
+            CASE WHEN a.attcollation <> t.typcollation THEN
+                (SELECT quote_ident(cn.nspname) || '.' || quote_ident(co.collname)
+                    FROM pg_collation co JOIN pg_namespace cn ON cn.oid = co.collnamespace
+                    WHERE co.oid = a.attcollation) END
+        FROM pg_attribute a
+            JOIN pg_class c ON c.oid = a.attrelid
+            JOIN pg_type t ON t.oid = a.atttypid
+            LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+        WHERE c.relkind IN ('r', 'p') AND a.attnum > 0 AND NOT a.attisdropped
+        ORDER BY a.attrelid, a.attnum";
+    for row in client.query(query, &[])? {
+        let (relid, name, quoted, data_type, not_null, generated, default, collation): (
+            u32,
+            String,
+            String,
+            String,
+            bool,
+            String,
+            Option<String>,
+            Option<String>,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+            row.get(6),
+            row.get(7),
+        );
+        let mut definition = format!("    {quoted} {data_type}");
+        if let Some(collation) = collation {
+            definition.push_str(&format!(" COLLATE {collation}"));
+        }
+        let mut separate_default = None;
+        match default {
+            Some(expr) if generated == "s" => {
+                definition.push_str(&format!(" GENERATED ALWAYS AS ({expr}) STORED"));
+            }
+            Some(expr) if expr.starts_with("nextval(") => {
+                separate_default = Some((name, quoted, expr));
+            }
+            Some(expr) => definition.push_str(&format!(" DEFAULT {expr}")),
+            None => (),
+        }
+        if not_null {
+            definition.push_str(" NOT NULL");
+        }
+        columns.entry(relid).or_default().push(Column {
+            definition,
+            separate_default,
+        });
+    }
+
+    let mut checks: HashMap<u32, Vec<String>> = HashMap::new();
+    let query = "SELECT c.conrelid, quote_ident(c.conname), pg_get_constraintdef(c.oid)
+        FROM pg_constraint c
+        WHERE c.contype = 'c' AND c.conrelid <> 0 AND c.conislocal
+        ORDER BY c.conrelid, c.conname";
+    for row in client.query(query, &[])? {
+        let (relid, name, definition): (u32, String, String) = (row.get(0), row.get(1), row.get(2));
+        checks
+            .entry(relid)
+            .or_default()
+            .push(format!("    CONSTRAINT {name} {definition}"));
+    }
+
+    let query = format!(
+        "SELECT c.oid, n.nspname::text, c.relname::text,
+            quote_ident(n.nspname) || '.' || quote_ident(c.relname),
+            pg_get_userbyid(c.relowner)::text, c.relpersistence::text,
+            CASE WHEN c.relkind = 'p' THEN pg_get_partkeydef(c.oid) END,
+            (SELECT quote_ident(pn.nspname) || '.' || quote_ident(p.relname)
+                FROM pg_inherits i
+                    JOIN pg_class p ON p.oid = i.inhparent
+                    JOIN pg_namespace pn ON pn.oid = p.relnamespace
+                WHERE i.inhrelid = c.oid AND c.relispartition),
+            CASE WHEN c.relispartition THEN pg_get_expr(c.relpartbound, c.oid) END,
+            c.relrowsecurity, c.relforcerowsecurity
+        FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind IN ('r', 'p') AND {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, c.relname",
+        not_extension_member("pg_class", "c.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (oid, namespace, name, qualified, owner, persistence): (
+            u32,
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+        );
+        let (partition_key, parent, bound): (Option<String>, Option<String>, Option<String>) =
+            (row.get(6), row.get(7), row.get(8));
+        let (row_security, force_row_security): (bool, bool) = (row.get(9), row.get(10));
+
+        let table_columns = columns.remove(&oid).unwrap_or_default();
+        let mut lines: Vec<String> = table_columns
+            .iter()
+            .map(|column| column.definition.clone())
+            .collect();
+        lines.extend(checks.remove(&oid).unwrap_or_default());
+        let unlogged = match persistence.as_str() {
+            "u" => "UNLOGGED ",
+            _ => "",
+        };
+        let mut create = format!(
+            "CREATE {unlogged}TABLE {qualified} (\n{}\n)",
+            lines.join(",\n")
+        );
+        if let Some(key) = partition_key {
+            create.push_str(&format!("\nPARTITION BY {key}"));
+        }
+        create.push(';');
+        push(
+            schema,
+            ObjectType::Table,
+            &namespace,
+            &name,
+            None,
+            &owner,
+            &[
+                create,
+                format!("ALTER TABLE {qualified} OWNER TO {};", quote_ident(&owner)),
+            ],
+        );
+
+        for (column, quoted, expr) in table_columns
+            .into_iter()
+            .filter_map(|column| column.separate_default)
+        {
+            push(
+                schema,
+                ObjectType::Default,
+                &namespace,
+                &format!("{name} {column}"),
+                None,
+                &owner,
+                &[format!(
+                    "ALTER TABLE ONLY {qualified} ALTER COLUMN {quoted} SET DEFAULT {expr};"
+                )],
+            );
+        }
+        if let (Some(parent), Some(bound)) = (parent, bound) {
+            push(
+                schema,
+                ObjectType::TableAttach,
+                &namespace,
+                &name,
+                None,
+                &owner,
+                &[format!(
+                    "ALTER TABLE ONLY {parent} ATTACH PARTITION {qualified} {bound};"
+                )],
+            );
+        }
+        if row_security {
+            let mut statements = vec![format!(
+                "ALTER TABLE {qualified} ENABLE ROW LEVEL SECURITY;"
+            )];
+            if force_row_security {
+                statements.push(format!("ALTER TABLE {qualified} FORCE ROW LEVEL SECURITY;"));
+            }
+            push(
+                schema,
+                ObjectType::RowSecurity,
+                &namespace,
+                &name,
+                None,
+                &owner,
+                &statements,
+            );
+        }
+    }
+    Ok(())
+}
+
+// pg_dump leaves out the bounds that are the default for the sequence type and direction
+fn sequence_options(
+    data_type: &str,
+    start: i64,
+    increment: i64,
+    min: i64,
+    max: i64,
+    cache: i64,
+) -> Vec<String> {
+    let (type_min, type_max) = match data_type {
+        "smallint" => (i16::MIN as i64, i16::MAX as i64),
+        "integer" => (i32::MIN as i64, i32::MAX as i64),
+        _ => (i64::MIN, i64::MAX),
+    };
+    let (default_min, default_max) = match increment > 0 {
+        true => (1, type_max),
+        false => (type_min, -1),
+    };
+    vec![
+        format!("START WITH {start}"),
+        format!("INCREMENT BY {increment}"),
+        match min == default_min {
+            true => "NO MINVALUE".to_string(),
+            false => format!("MINVALUE {min}"),
+        },
+        match max == default_max {
+            true => "NO MAXVALUE".to_string(),
+            false => format!("MAXVALUE {max}"),
+        },
+        format!("CACHE {cache}"),
+    ]
+}
+
+fn read_sequences(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text,
+            quote_ident(n.nspname) || '.' || quote_ident(c.relname),
+            pg_get_userbyid(c.relowner)::text, format_type(s.seqtypid, NULL),
+            s.seqstart, s.seqincrement, s.seqmin, s.seqmax, s.seqcache, s.seqcycle,
+            d.deptype::text, quote_ident(tn.nspname) || '.' || quote_ident(t.relname),
+            quote_ident(a.attname), a.attidentity::text
+        FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            JOIN pg_sequence s ON s.seqrelid = c.oid
+            LEFT JOIN pg_depend d ON d.classid = 'pg_class'::regclass AND d.objid = c.oid
+                AND d.refclassid = 'pg_class'::regclass AND d.deptype IN ('a', 'i')
+            LEFT JOIN pg_class t ON t.oid = d.refobjid
+            LEFT JOIN pg_namespace tn ON tn.oid = t.relnamespace
+            LEFT JOIN pg_attribute a ON a.attrelid = d.refobjid AND a.attnum = d.refobjsubid
+        WHERE c.relkind = 'S' AND {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, c.relname",
+        not_extension_member("pg_class", "c.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, name, qualified, owner, data_type): (
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4));
+        let (start, increment, min, max, cache, cycle): (i64, i64, i64, i64, i64, bool) = (
+            row.get(5),
+            row.get(6),
+            row.get(7),
+            row.get(8),
+            row.get(9),
+            row.get(10),
+        );
+        let (dependency, table, column, identity): (
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ) = (row.get(11), row.get(12), row.get(13), row.get(14));
+        let mut options = sequence_options(&data_type, start, increment, min, max, cache);
+        if cycle {
+            options.push("CYCLE".to_string());
+        }
+
+        match (dependency.as_deref(), table, column) {
+            // identity columns own their sequence and are dumped as part of the table
+            (Some("i"), Some(table), Some(column)) => {
+                let generated = match identity.as_deref() {
+                    Some("d") => "BY DEFAULT",
+                    _ => "ALWAYS",
+                };
+                push(
+                    schema,
+                    ObjectType::Sequence,
+                    &namespace,
+                    &name,
+                    None,
+                    &owner,
+                    &[format!(
+                        "ALTER TABLE {table} ALTER COLUMN {column} ADD GENERATED {generated} AS IDENTITY (\n    SEQUENCE NAME {qualified}\n    {}\n);",
+                        options.join("\n    ")
+                    )],
+                );
+            }
+            (dependency, table, column) => {
+                if data_type != "bigint" {
+                    options.insert(0, format!("AS {data_type}"));
+                }
+                push(
+                    schema,
+                    ObjectType::Sequence,
+                    &namespace,
+                    &name,
+                    None,
+                    &owner,
+                    &[
+                        format!(
+                            "CREATE SEQUENCE {qualified}\n    {};",
+                            options.join("\n    ")
+                        ),
+                        format!("ALTER TABLE {qualified} OWNER TO {};", quote_ident(&owner)),
+                    ],
+                );
+                if let (Some("a"), Some(table), Some(column)) = (dependency, table, column) {
+                    push(
+                        schema,
+                        ObjectType::SequenceOwnedBy,
+                        &namespace,
+                        &name,
+                        None,
+                        &owner,
+                        &[format!(
+                            "ALTER SEQUENCE {qualified} OWNED BY {table}.{column};"
+                        )],
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_views(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text,
+            quote_ident(n.nspname) || '.' || quote_ident(c.relname),
+            pg_get_userbyid(c.relowner)::text, c.relkind::text, pg_get_viewdef(c.oid)
+        FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind IN ('v', 'm') AND {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, c.relname",
+        not_extension_member("pg_class", "c.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, name, qualified, owner, kind, definition): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+        );
+        // the dump is schema only, so materialized views are never refreshed
+        let (object_type, create) = match kind.as_str() {
+            "m" => (
+                ObjectType::MaterializedView,
+                format!(
+                    "CREATE MATERIALIZED VIEW {qualified} AS\n{}\n  WITH NO DATA;",
+                    definition.trim_end().trim_end_matches(';')
+                ),
+            ),
+            _ => (
+                ObjectType::View,
+                format!("CREATE VIEW {qualified} AS\n{definition}"),
+            ),
+        };
+        push(
+            schema,
+            object_type,
+            &namespace,
+            &name,
+            None,
+            &owner,
+            &[
+                create,
+                format!("ALTER TABLE {qualified} OWNER TO {};", quote_ident(&owner)),
+            ],
+        );
+    }
+    Ok(())
+}
+
+fn read_constraints(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text, co.conname::text,
+            quote_ident(n.nspname) || '.' || quote_ident(c.relname), quote_ident(co.conname),
+            pg_get_userbyid(c.relowner)::text, co.contype::text, pg_get_constraintdef(co.oid),
+            c.relkind::text
+        FROM pg_constraint co
+            JOIN pg_class c ON c.oid = co.conrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE co.contype IN ('p', 'u', 'x', 'f') AND co.conparentid = 0
+            AND {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, c.relname, co.conname",
+        not_extension_member("pg_class", "c.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, table, name, qualified, quoted, owner, kind, definition, relkind): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+            row.get(6),
+            row.get(7),
+            row.get(8),
+        );
+        let object_type = match kind.as_str() {
+            "f" => ObjectType::FkConstraint,
+            _ => ObjectType::Constraint,
+        };
+        // constraints on a partitioned table cascade to its partitions
+        let only = match relkind.as_str() {
+            "p" => "",
+            _ => " ONLY",
+        };
+        push(
+            schema,
+            object_type,
+            &namespace,
+            &format!("{table} {name}"),
+            None,
+            &owner,
+            &[format!(
+                "ALTER TABLE{only} {qualified}\n    ADD CONSTRAINT {quoted} {definition};"
+            )],
+        );
+    }
+    Ok(())
+}
+
+fn read_indexes(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, i.relname::text, pg_get_userbyid(c.relowner)::text,
+            pg_get_indexdef(i.oid),
+            (SELECT quote_ident(pn.nspname) || '.' || quote_ident(p.relname)
+                FROM pg_inherits h
+                    JOIN pg_class p ON p.oid = h.inhparent
+                    JOIN pg_namespace pn ON pn.oid = p.relnamespace
+                WHERE h.inhrelid = i.oid),
+            quote_ident(n.nspname) || '.' || quote_ident(i.relname)
+        FROM pg_index x
+            JOIN pg_class i ON i.oid = x.indexrelid
+            JOIN pg_class c ON c.oid = x.indrelid
+            JOIN pg_namespace n ON n.oid = i.relnamespace
+        WHERE c.relkind IN ('r', 'p', 'm') AND {USER_NAMESPACE} AND {}
+            AND NOT EXISTS (SELECT 1 FROM pg_constraint co
+                WHERE co.conindid = i.oid AND co.contype IN ('p', 'u', 'x'))
+        ORDER BY n.nspname, i.relname",
+        not_extension_member("pg_class", "c.oid")
+    );
+    let mut attachments = Vec::new();
+    for row in client.query(&query, &[])? {
+        let (namespace, name, owner, definition, parent, qualified): (
+            String,
+            String,
+            String,
+            String,
+            Option<String>,
+            String,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+        );
+        push(
+            schema,
+            ObjectType::Index,
+            &namespace,
+            &name,
+            None,
+            &owner,
+            &[format!("{definition};")],
+        );
+        if let Some(parent) = parent {
+            attachments.push((namespace, name, owner, parent, qualified));
+        }
+    }
+    // attached once every index exists, the same as pg_dump
+    for (namespace, name, owner, parent, qualified) in attachments {
+        push(
+            schema,
+            ObjectType::IndexAttach,
+            &namespace,
+            &name,
+            None,
+            &owner,
+            &[format!(
+                "ALTER INDEX {parent} ATTACH PARTITION {qualified};"
+            )],
+        );
+    }
+    Ok(())
+}
+
+fn read_triggers(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text, t.tgname::text,
+            pg_get_userbyid(c.relowner)::text, pg_get_triggerdef(t.oid)
+        FROM pg_trigger t
+            JOIN pg_class c ON c.oid = t.tgrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE NOT t.tgisinternal AND t.tgparentid = 0 AND {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, c.relname, t.tgname",
+        not_extension_member("pg_class", "c.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, table, name, owner, definition): (String, String, String, String, String) =
+            (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4));
+        push(
+            schema,
+            ObjectType::Trigger,
+            &namespace,
+            &format!("{table} {name}"),
+            None,
+            &owner,
+            &[format!("{definition};")],
+        );
+    }
+    Ok(())
+}
+
+fn read_statistics(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, s.stxname::text,
+            quote_ident(n.nspname) || '.' || quote_ident(s.stxname),
+            pg_get_userbyid(s.stxowner)::text, pg_get_statisticsobjdef(s.oid)
+        FROM pg_statistic_ext s JOIN pg_namespace n ON n.oid = s.stxnamespace
+        WHERE {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, s.stxname",
+        not_extension_member("pg_statistic_ext", "s.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, name, qualified, owner, definition): (
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4));
+        push(
+            schema,
+            ObjectType::Statistics,
+            &namespace,
+            &name,
+            None,
+            &owner,
+            &[
+                format!("{definition};"),
+                format!(
+                    "ALTER STATISTICS {qualified} OWNER TO {};",
+                    quote_ident(&owner)
+                ),
+            ],
+        );
+    }
+    Ok(())
+}
+
+fn read_event_triggers(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT e.evtname::text, quote_ident(e.evtname), pg_get_userbyid(e.evtowner)::text,
+            e.evtevent::text, e.evtfoid::regproc::text,
+            (SELECT string_agg(quote_literal(tag), ', ') FROM unnest(e.evttags) tag),
+            e.evtenabled::text
+        FROM pg_event_trigger e
+        WHERE {}
+        ORDER BY e.evtname",
+        not_extension_member("pg_event_trigger", "e.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (name, quoted, owner, event, function, tags, enabled): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            Option<String>,
+            String,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+            row.get(6),
+        );
+        let mut create = format!("CREATE EVENT TRIGGER {quoted} ON {event}");
+        if let Some(tags) = tags {
+            create.push_str(&format!("\n         WHEN TAG IN ({tags})"));
+        }
+        create.push_str(&format!("\n   EXECUTE FUNCTION {function}();"));
+        let mut statements = vec![create];
+        match enabled.as_str() {
+            "D" => statements.push(format!("ALTER EVENT TRIGGER {quoted} DISABLE;")),
+            "R" => statements.push(format!("ALTER EVENT TRIGGER {quoted} ENABLE REPLICA;")),
+            "A" => statements.push(format!("ALTER EVENT TRIGGER {quoted} ENABLE ALWAYS;")),
+            _ => (),
+        }
+        statements.push(format!(
+            "ALTER EVENT TRIGGER {quoted} OWNER TO {};",
+            quote_ident(&owner)
+        ));
+        push(
+            schema,
+            ObjectType::EventTrigger,
+            "-",
+            &name,
+            None,
+            &owner,
+            &statements,
+        );
+    }
+    Ok(())
+}
+
+fn read_rules(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    // _RETURN rules are how views are implemented and come with CREATE VIEW
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text, r.rulename::text,
+            pg_get_userbyid(c.relowner)::text, pg_get_ruledef(r.oid)
+        FROM pg_rewrite r
+            JOIN pg_class c ON c.oid = r.ev_class
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE r.rulename <> '_RETURN' AND {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, c.relname, r.rulename",
+        not_extension_member("pg_class", "c.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, table, name, owner, definition): (String, String, String, String, String) =
+            (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4));
+        push(
+            schema,
+            ObjectType::Rule,
+            &namespace,
+            &format!("{table} {name}"),
+            None,
+            &owner,
+            &[definition],
+        );
+    }
+    Ok(())
+}
+
+fn read_policies(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, c.relname::text, p.polname::text, quote_ident(p.polname),
+            quote_ident(n.nspname) || '.' || quote_ident(c.relname),
+            pg_get_userbyid(c.relowner)::text, p.polpermissive, p.polcmd::text,
+            CASE WHEN p.polroles = '{{0}}' THEN NULL ELSE
+                (SELECT string_agg(quote_ident(r.rolname), ', ' ORDER BY r.rolname)
+                    FROM pg_roles r WHERE r.oid = ANY (p.polroles)) END,
+            pg_get_expr(p.polqual, p.polrelid), pg_get_expr(p.polwithcheck, p.polrelid)
+        FROM pg_policy p
+            JOIN pg_class c ON c.oid = p.polrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE {USER_NAMESPACE} AND {}
+        ORDER BY n.nspname, c.relname, p.polname",
+        not_extension_member("pg_class", "c.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, table, name, quoted, qualified, owner): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+        );
+        let (permissive, command, roles, using, check): (
+            bool,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ) = (row.get(6), row.get(7), row.get(8), row.get(9), row.get(10));
+        let mut create = format!("CREATE POLICY {quoted} ON {qualified}");
+        if !permissive {
+            create.push_str(" AS RESTRICTIVE");
+        }
+        match command.as_str() {
+            "r" => create.push_str(" FOR SELECT"),
+            "a" => create.push_str(" FOR INSERT"),
+            "w" => create.push_str(" FOR UPDATE"),
+            "d" => create.push_str(" FOR DELETE"),
+            _ => (),
+        }
+        if let Some(roles) = roles {
+            create.push_str(&format!(" TO {roles}"));
+        }
+        if let Some(using) = using {
+            create.push_str(&format!(" USING ({using})"));
+        }
+        if let Some(check) = check {
+            create.push_str(&format!(" WITH CHECK ({check})"));
+        }
+        create.push(';');
+        push(
+            schema,
+            ObjectType::Policy,
+            &namespace,
+            &format!("{table} {name}"),
+            None,
+            &owner,
+            &[create],
+        );
+    }
+    Ok(())
+}
+
+fn read_comments(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    // every row is (schema, kind, name, target, owner, literal), where the name is what pg_dump
+    // puts in the header and the target is what goes in the COMMENT ON statement
+    let query = format!(
+        "SELECT n.nspname::text,
+            CASE WHEN d.objsubid > 0 THEN 'COLUMN'
+                WHEN c.relkind = 'v' THEN 'VIEW'
+                WHEN c.relkind = 'm' THEN 'MATERIALIZED VIEW'
+                WHEN c.relkind = 'S' THEN 'SEQUENCE'
+                WHEN c.relkind IN ('i', 'I') THEN 'INDEX'
+                WHEN c.relkind = 'f' THEN 'FOREIGN TABLE'
+                ELSE 'TABLE' END,
+            quote_ident(c.relname) || COALESCE('.' || quote_ident(a.attname), ''),
+            quote_ident(n.nspname) || '.' || quote_ident(c.relname)
+                || COALESCE('.' || quote_ident(a.attname), ''),
+            pg_get_userbyid(c.relowner)::text, quote_literal(d.description)
+        FROM pg_description d
+            JOIN pg_class c ON d.classoid = 'pg_class'::regclass AND c.oid = d.objoid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            LEFT JOIN pg_attribute a ON d.objsubid > 0 AND a.attrelid = c.oid
+                AND a.attnum = d.objsubid
+        WHERE {USER_NAMESPACE} AND {class}
+        UNION ALL
+        SELECT n.nspname::text, CASE WHEN p.prokind = 'p' THEN 'PROCEDURE' ELSE 'FUNCTION' END,
+            quote_ident(p.proname) || '(' || pg_get_function_identity_arguments(p.oid) || ')',
+            quote_ident(n.nspname) || '.' || quote_ident(p.proname)
+                || '(' || pg_get_function_identity_arguments(p.oid) || ')',
+            pg_get_userbyid(p.proowner)::text, quote_literal(d.description)
+        FROM pg_description d
+            JOIN pg_proc p ON d.classoid = 'pg_proc'::regclass AND p.oid = d.objoid
+            JOIN pg_namespace n ON n.oid = p.pronamespace
+        WHERE {USER_NAMESPACE} AND {proc}
+        UNION ALL
+        SELECT n.nspname::text, CASE WHEN t.typtype = 'd' THEN 'DOMAIN' ELSE 'TYPE' END,
+            quote_ident(t.typname), quote_ident(n.nspname) || '.' || quote_ident(t.typname),
+            pg_get_userbyid(t.typowner)::text, quote_literal(d.description)
+        FROM pg_description d
+            JOIN pg_type t ON d.classoid = 'pg_type'::regclass AND t.oid = d.objoid
+            JOIN pg_namespace n ON n.oid = t.typnamespace
+        WHERE {USER_NAMESPACE} AND {pg_type}
+        UNION ALL
+        SELECT '-', 'SCHEMA', quote_ident(n.nspname), quote_ident(n.nspname),
+            pg_get_userbyid(n.nspowner)::text, quote_literal(d.description)
+        FROM pg_description d
+            JOIN pg_namespace n ON d.classoid = 'pg_namespace'::regclass AND n.oid = d.objoid
+        WHERE {USER_NAMESPACE} AND {namespace}
+            AND (n.nspname <> 'public' OR d.description <> 'standard public schema')
+        UNION ALL
+        SELECT n.nspname::text, 'CONSTRAINT',
+            quote_ident(co.conname) || ' ON ' || quote_ident(c.relname),
+            quote_ident(co.conname) || ' ON ' || quote_ident(n.nspname) || '.'
+                || quote_ident(c.relname),
+            pg_get_userbyid(c.relowner)::text, quote_literal(d.description)
+        FROM pg_description d
+            JOIN pg_constraint co ON d.classoid = 'pg_constraint'::regclass AND co.oid = d.objoid
+            JOIN pg_class c ON c.oid = co.conrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE {USER_NAMESPACE} AND {class}
+        UNION ALL
+        SELECT n.nspname::text, 'TRIGGER',
+            quote_ident(t.tgname) || ' ON ' || quote_ident(c.relname),
+            quote_ident(t.tgname) || ' ON ' || quote_ident(n.nspname) || '.'
+                || quote_ident(c.relname),
+            pg_get_userbyid(c.relowner)::text, quote_literal(d.description)
+        FROM pg_description d
+            JOIN pg_trigger t ON d.classoid = 'pg_trigger'::regclass AND t.oid = d.objoid
+            JOIN pg_class c ON c.oid = t.tgrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE {USER_NAMESPACE} AND {class}
+        UNION ALL
+        SELECT '-', 'EXTENSION', quote_ident(e.extname), quote_ident(e.extname), '',
+            quote_literal(d.description)
+        FROM pg_description d
+            JOIN pg_extension e ON d.classoid = 'pg_extension'::regclass AND e.oid = d.objoid
+        WHERE e.extname <> 'plpgsql'
+        ORDER BY 1, 2, 3",
+        class = not_extension_member("pg_class", "c.oid"),
+        proc = not_extension_member("pg_proc", "p.oid"),
+        pg_type = not_extension_member("pg_type", "t.oid"),
+        namespace = not_extension_member("pg_namespace", "n.oid"),
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, kind, name, target, owner, literal): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+        );
+        let (name, signature) = split_signature(&name);
+        push(
+            schema,
+            ObjectType::Comment,
+            &namespace,
+            &format!("{kind} {name}"),
+            signature,
+            &owner,
+            &[format!("COMMENT ON {kind} {target} IS {literal};")],
+        );
+    }
+    Ok(())
+}
+
+// `total(a integer)` into `total` and `(a integer)`, the way the dump headers are parsed
+fn split_signature(name: &str) -> (&str, Option<String>) {
+    match name.split_once('(') {
+        Some((name, args)) => (name, Some(format!("({args}"))),
+        None => (name, None),
+    }
+}
+
+// the privileges an aclitem letter stands for, in the order pg_dump lists them
+const PRIVILEGES: [(char, &str); 13] = [
+    ('r', "SELECT"),
+    ('a', "INSERT"),
+    ('x', "REFERENCES"),
+    ('d', "DELETE"),
+    ('t', "TRIGGER"),
+    ('D', "TRUNCATE"),
+    ('m', "MAINTAIN"),
+    ('X', "EXECUTE"),
+    ('C', "CREATE"),
+    ('c', "CONNECT"),
+    ('T', "TEMPORARY"),
+    ('U', "USAGE"),
+    ('w', "UPDATE"),
+];
+
+// the privileges a column grant can hold
+const COLUMN_PRIVILEGES: &str = "arwx";
+
+// `api=r*w/postgres` into the grantee and its privileges, with and without grant option
+fn parse_acl_item(item: &str) -> Option<(String, String, String)> {
+    let (grantee, rest) = item.rsplit_once('=')?;
+    let (privileges, _grantor) = rest.split_once('/')?;
+    // aclitem quotes only names with special characters, and PUBLIC is left empty
+    let grantee = match grantee {
+        "" => "PUBLIC".to_string(),
+        grantee => match grantee.strip_prefix('"').and_then(|g| g.strip_suffix('"')) {
+            Some(quoted) => quote_ident(&quoted.replace("\"\"", "\"")),
+            None => quote_ident(grantee),
+        },
+    };
+    let mut plain = String::new();
+    let mut grantable = String::new();
+    let mut letters = privileges.chars().peekable();
+    while let Some(letter) = letters.next() {
+        match letters.peek() {
+            Some('*') => {
+                letters.next();
+                grantable.push(letter);
+            }
+            _ => plain.push(letter),
+        }
+    }
+    Some((grantee, plain, grantable))
+}
+
+// column privileges are listed as `SELECT(email)`, one column at a time
+fn privilege_list(letters: &str, all: &str, column: Option<&str>) -> String {
+    let column = column
+        .map(|column| format!("({column})"))
+        .unwrap_or_default();
+    let mut sorted: Vec<char> = letters.chars().collect();
+    let mut full: Vec<char> = all.chars().collect();
+    sorted.sort_unstable();
+    full.sort_unstable();
+    if sorted == full {
+        return format!("ALL{column}");
+    }
+    PRIVILEGES
+        .iter()
+        .filter(|(letter, _)| letters.contains(*letter))
+        .map(|(_, privilege)| format!("{privilege}{column}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// everything the owner holds by default, which a GRANT writes as ALL
+fn owner_privileges(owner: &str, default_acl: &[String]) -> String {
+    let owner = quote_ident(owner);
+    default_acl
+        .iter()
+        .filter_map(|item| parse_acl_item(item))
+        .find(|(grantee, _, _)| *grantee == owner)
+        .map(|(_, plain, grantable)| format!("{plain}{grantable}"))
+        .unwrap_or_default()
+}
+
+// only the difference from the default privileges is dumped, as REVOKEs of what the default
+// grants but the object doesn't and GRANTs of the rest
+fn acl_statements(
+    kind: &str,
+    target: &str,
+    column: Option<&str>,
+    all: &str,
+    acl: &[String],
+    default_acl: &[String],
+) -> Vec<String> {
+    let actual: Vec<_> = acl.iter().filter_map(|item| parse_acl_item(item)).collect();
+    let default: Vec<_> = default_acl
+        .iter()
+        .filter_map(|item| parse_acl_item(item))
+        .collect();
+
+    let mut statements = Vec::new();
+    for item in &default {
+        if !actual.contains(item) {
+            statements.push(format!("REVOKE ALL ON {kind} {target} FROM {};", item.0));
+        }
+    }
+    for item in &actual {
+        if default.contains(item) {
+            continue;
+        }
+        let (grantee, plain, grantable) = item;
+        if !plain.is_empty() {
+            statements.push(format!(
+                "GRANT {} ON {kind} {target} TO {grantee};",
+                privilege_list(plain, all, column)
+            ));
+        }
+        if !grantable.is_empty() {
+            statements.push(format!(
+                "GRANT {} ON {kind} {target} TO {grantee} WITH GRANT OPTION;",
+                privilege_list(grantable, all, column)
+            ));
+        }
+    }
+    statements
+}
+
+fn read_acls(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT n.nspname::text, CASE WHEN c.relkind = 'S' THEN 'SEQUENCE' ELSE 'TABLE' END,
+            quote_ident(c.relname), quote_ident(n.nspname) || '.' || quote_ident(c.relname),
+            pg_get_userbyid(c.relowner)::text, c.relacl::text[],
+            acldefault((CASE WHEN c.relkind = 'S' THEN 's' ELSE 'r' END)::\"char\", c.relowner)::text[]
+        FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind IN ('r', 'p', 'v', 'm', 'S', 'f') AND c.relacl IS NOT NULL
+            AND {USER_NAMESPACE} AND {class}
+        UNION ALL
+        SELECT n.nspname::text, CASE WHEN p.prokind = 'p' THEN 'PROCEDURE' ELSE 'FUNCTION' END,
+            quote_ident(p.proname) || '(' || pg_get_function_identity_arguments(p.oid) || ')',
+            quote_ident(n.nspname) || '.' || quote_ident(p.proname)
+                || '(' || pg_get_function_identity_arguments(p.oid) || ')',
+            pg_get_userbyid(p.proowner)::text, p.proacl::text[],
+            acldefault('f'::\"char\", p.proowner)::text[]
+        FROM pg_proc p JOIN pg_namespace n ON n.oid = p.pronamespace
+        WHERE p.proacl IS NOT NULL AND {USER_NAMESPACE} AND {proc}
+        UNION ALL
+        SELECT n.nspname::text, CASE WHEN t.typtype = 'd' THEN 'DOMAIN' ELSE 'TYPE' END,
+            quote_ident(t.typname), quote_ident(n.nspname) || '.' || quote_ident(t.typname),
+            pg_get_userbyid(t.typowner)::text, t.typacl::text[],
+            acldefault('T'::\"char\", t.typowner)::text[]
+        FROM pg_type t JOIN pg_namespace n ON n.oid = t.typnamespace
+        WHERE t.typacl IS NOT NULL AND {USER_NAMESPACE} AND {pg_type}
+        UNION ALL
+        SELECT '-', 'SCHEMA', quote_ident(n.nspname), quote_ident(n.nspname),
+            pg_get_userbyid(n.nspowner)::text, n.nspacl::text[],
+            CASE WHEN n.nspname = 'public'
+                THEN '{{pg_database_owner=UC/pg_database_owner,=U/pg_database_owner}}'
+                ELSE acldefault('n'::\"char\", n.nspowner)::text[] END
+        FROM pg_namespace n
+        WHERE n.nspacl IS NOT NULL AND {USER_NAMESPACE} AND {namespace}
+        ORDER BY 1, 2, 3",
+        class = not_extension_member("pg_class", "c.oid"),
+        proc = not_extension_member("pg_proc", "p.oid"),
+        pg_type = not_extension_member("pg_type", "t.oid"),
+        namespace = not_extension_member("pg_namespace", "n.oid"),
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, kind, name, target, owner, acl, default_acl): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            Vec<String>,
+            Vec<String>,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+            row.get(6),
+        );
+        let all = owner_privileges(&owner, &default_acl);
+        let statements = acl_statements(&kind, &target, None, &all, &acl, &default_acl);
+        if statements.is_empty() {
+            continue;
+        }
+        let (name, signature) = split_signature(&name);
+        push(
+            schema,
+            ObjectType::Acl,
+            &namespace,
+            &format!("{kind} {name}"),
+            signature,
+            &owner,
+            &[statements.join("\n")],
+        );
+    }
+
+    // column grants have no default, everything in attacl is dumped
+    let query = format!(
+        "SELECT n.nspname::text, quote_ident(c.relname) || '.' || quote_ident(a.attname),
+            quote_ident(n.nspname) || '.' || quote_ident(c.relname), quote_ident(a.attname),
+            pg_get_userbyid(c.relowner)::text, a.attacl::text[]
+        FROM pg_attribute a
+            JOIN pg_class c ON c.oid = a.attrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE a.attacl IS NOT NULL AND a.attnum > 0 AND NOT a.attisdropped
+            AND {USER_NAMESPACE} AND {}
+        ORDER BY 1, 2",
+        not_extension_member("pg_class", "c.oid")
+    );
+    for row in client.query(&query, &[])? {
+        let (namespace, name, target, column, owner, acl): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            Vec<String>,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+        );
+        let statements = acl_statements(
+            "TABLE",
+            &target,
+            Some(&column),
+            COLUMN_PRIVILEGES,
+            &acl,
+            &[],
+        );
+        if statements.is_empty() {
+            continue;
+        }
+        push(
+            schema,
+            ObjectType::Acl,
+            &namespace,
+            &format!("COLUMN {name}"),
+            None,
+            &owner,
+            &[statements.join("\n")],
+        );
+    }
+    Ok(())
+}
+
+// global entries replace the built-in defaults and are dumped as the difference from them,
+// entries for a schema only add to them and are dumped whole. Entries of one kind share a name
+// and stay in the order pg_dump reads them in
+fn read_default_acls(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = "SELECT coalesce(n.nspname::text, '-'), quote_ident(n.nspname),
+            CASE d.defaclobjtype WHEN 'r' THEN 'TABLES' WHEN 'S' THEN 'SEQUENCES'
+                WHEN 'f' THEN 'FUNCTIONS' WHEN 'T' THEN 'TYPES' ELSE 'SCHEMAS' END,
+            pg_get_userbyid(d.defaclrole)::text, d.defaclacl::text[],
+            acldefault(CASE WHEN d.defaclobjtype = 'S' THEN 's' ELSE d.defaclobjtype END,
+                d.defaclrole)::text[]
+        FROM pg_default_acl d LEFT JOIN pg_namespace n ON n.oid = d.defaclnamespace
+        ORDER BY 3, d.oid";
+    for row in client.query(query, &[])? {
+        let (namespace, quoted, kind, owner, acl, builtin): (
+            String,
+            Option<String>,
+            String,
+            String,
+            Vec<String>,
+            Vec<String>,
+        ) = (
+            row.get(0),
+            row.get(1),
+            row.get(2),
+            row.get(3),
+            row.get(4),
+            row.get(5),
+        );
+        let default_acl = match quoted {
+            Some(_) => &[][..],
+            None => &builtin[..],
+        };
+        let all = owner_privileges(&owner, &builtin);
+        let prefix = match &quoted {
+            Some(quoted) => format!(
+                "ALTER DEFAULT PRIVILEGES FOR ROLE {} IN SCHEMA {quoted}",
+                quote_ident(&owner)
+            ),
+            None => format!("ALTER DEFAULT PRIVILEGES FOR ROLE {}", quote_ident(&owner)),
+        };
+        // pg_dump leaves the object name empty, hence the two spaces after the kind
+        let statements: Vec<_> = acl_statements(&kind, "", None, &all, &acl, default_acl)
+            .into_iter()
+            .map(|statement| format!("{prefix} {statement}"))
+            .collect();
+        if statements.is_empty() {
+            continue;
+        }
+        push(
+            schema,
+            ObjectType::DefaultAcl,
+            &namespace,
+            &format!("DEFAULT PRIVILEGES FOR {kind}"),
+            None,
+            &owner,
+            &[statements.join("\n")],
+        );
+    }
+    Ok(())
+}
+
+// objects this backend doesn't rebuild yet are reported rather than silently left out, so
+// --strict can refuse an incomplete tree
+fn report_unsupported(client: &mut Client, schema: &mut Schema) -> Result<(), postgres::Error> {
+    let query = format!(
+        "SELECT 'COLLATION', n.nspname || '.' || c.collname
+        FROM pg_collation c JOIN pg_namespace n ON n.oid = c.collnamespace
+        WHERE {USER_NAMESPACE} AND {collation}
+        UNION ALL
+        SELECT 'TEXT SEARCH CONFIGURATION', n.nspname || '.' || c.cfgname
+        FROM pg_ts_config c JOIN pg_namespace n ON n.oid = c.cfgnamespace
+        WHERE {USER_NAMESPACE} AND {ts_config}
+        UNION ALL
+        SELECT 'TEXT SEARCH DICTIONARY', n.nspname || '.' || d.dictname
+        FROM pg_ts_dict d JOIN pg_namespace n ON n.oid = d.dictnamespace
+        WHERE {USER_NAMESPACE} AND {ts_dict}
+        UNION ALL
+        SELECT 'OPERATOR', n.nspname || '.' || o.oprname
+        FROM pg_operator o JOIN pg_namespace n ON n.oid = o.oprnamespace
+        WHERE {USER_NAMESPACE} AND {operator}
+        UNION ALL
+        SELECT 'CAST', format_type(c.castsource, NULL) || ' AS ' || format_type(c.casttarget, NULL)
+        FROM pg_cast c
+        WHERE c.oid >= 16384 AND {cast}
+        UNION ALL
+        SELECT 'FOREIGN DATA WRAPPER', w.fdwname::text
+        FROM pg_foreign_data_wrapper w WHERE {fdw}
+        UNION ALL
+        SELECT 'SERVER', s.srvname::text
+        FROM pg_foreign_server s WHERE {server}
+        UNION ALL
+        SELECT 'PUBLICATION', p.pubname::text FROM pg_publication p
+        UNION ALL
+        SELECT 'SUBSCRIPTION', s.subname::text
+        FROM pg_subscription s
+        WHERE s.subdbid = (SELECT oid FROM pg_database WHERE datname = current_database())
+        ORDER BY 1, 2",
+        collation = not_extension_member("pg_collation", "c.oid"),
+        ts_config = not_extension_member("pg_ts_config", "c.oid"),
+        ts_dict = not_extension_member("pg_ts_dict", "d.oid"),
+        operator = not_extension_member("pg_operator", "o.oid"),
+        cast = not_extension_member("pg_cast", "c.oid"),
+        fdw = not_extension_member("pg_foreign_data_wrapper", "w.oid"),
+        server = not_extension_member("pg_foreign_server", "s.oid"),
+    );
+    for row in client.query(&query, &[])? {
+        let (kind, name): (String, String) = (row.get(0), row.get(1));
+        schema.report(Diagnostic {
+            line: None,
+            header: format!("{kind} {name}"),
+            reason: "not read from the catalog, use the pg_dump backend".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// these need a server to read from, WILLPG_TEST_DATABASE_URL names a database the tests can
+// create their fixtures from, e.g. postgresql://postgres@localhost/postgres. Without one they
+// pass without checking anything
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    };
+
+    use clap::{Args, FromArgMatches};
+
+    use super::*;
+    use crate::input::{PgDumpOptions, get_dump};
+    use crate::structs::WriteOptions;
+
+    // a database of its own, dropped even when the test fails
+    struct Fixture {
+        admin: Client,
+        name: String,
+        url: String,
+    }
+
+    impl Fixture {
+        fn create(fixture: &str, sql: &str) -> Option<Fixture> {
+            let Ok(admin_url) = std::env::var("WILLPG_TEST_DATABASE_URL") else {
+                eprintln!("WILLPG_TEST_DATABASE_URL is not set, skipping");
+                return None;
+            };
+            let mut admin = match Client::connect(&admin_url, NoTls) {
+                Ok(admin) => admin,
+                Err(e) => {
+                    eprintln!("can't connect to {admin_url}, skipping: {e}");
+                    return None;
+                }
+            };
+            let name = format!("willpg_test_{}_{fixture}", std::process::id());
+            // one at a time, neither runs in a transaction
+            for statement in ["DROP DATABASE IF EXISTS", "CREATE DATABASE"] {
+                admin.batch_execute(&format!("{statement} {name}")).unwrap();
+            }
+            let fixture = Fixture {
+                url: with_database(&admin_url, &name),
+                admin,
+                name,
+            };
+            Client::connect(&fixture.url, NoTls)
+                .unwrap()
+                .batch_execute(sql)
+                .unwrap();
+            Some(fixture)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let drop = format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", self.name);
+            let _ = self.admin.batch_execute(&drop);
+        }
+    }
+
+    // postgresql://postgres@localhost/postgres?host=/tmp to the same server's `name` database
+    fn with_database(url: &str, name: &str) -> String {
+        let (base, params) = match url.split_once('?') {
+            Some((base, params)) => (base, format!("?{params}")),
+            None => (url, String::new()),
+        };
+        let server = match base.split_once("://") {
+            Some((scheme, rest)) => {
+                format!("{scheme}://{}", rest.split('/').next().unwrap_or_default())
+            }
+            None => base.to_string(),
+        };
+        format!("{server}/{name}{params}")
+    }
+
+    // both backends render the fixture into the same tree, but for the prologue: the catalog
+    // has no SET lines of pg_dump's to repeat
+    fn render_both(url: &str) -> BTreeMap<PathBuf, String> {
+        let options = WriteOptions {
+            strip_volatile: true,
+            ..WriteOptions::default()
+        };
+        // clap's defaults rather than Default's, so pg_dump is looked up on the PATH
+        let command = PgDumpOptions::augment_args(clap::Command::new("willpg"));
+        let pg_dump = PgDumpOptions::from_arg_matches(&command.get_matches_from(["willpg"]));
+        let dumped = get_dump(url, &pg_dump.unwrap())
+            .unwrap()
+            .parse::<Schema>()
+            .unwrap()
+            .render(&options)
+            .unwrap();
+        let read = get_schema(url).unwrap().render(&options).unwrap();
+        let prologue = Path::new("00_prologue.sql");
+        let paths = |files: &BTreeMap<PathBuf, String>| -> Vec<PathBuf> {
+            files.keys().filter(|fp| *fp != prologue).cloned().collect()
+        };
+        assert_eq!(paths(&dumped), paths(&read));
+        for fp in paths(&dumped) {
+            assert_eq!(dumped[&fp], read[&fp], "{} differs", fp.display());
+        }
+        read
+    }
+
+    #[test]
+    fn functions_match_pg_dump() {
+        let Some(fixture) = Fixture::create(
+            "functions",
+            "CREATE SCHEMA shop;
+            CREATE FUNCTION shop.total(a text) RETURNS integer LANGUAGE sql STABLE STRICT
+                SECURITY DEFINER LEAKPROOF COST 5 PARALLEL SAFE SET search_path = shop, pg_temp
+                AS $$ SELECT length(a) $$;
+            CREATE FUNCTION shop.total(a integer, b integer DEFAULT 2) RETURNS integer
+                LANGUAGE sql AS $$ SELECT a + b $$;
+            CREATE FUNCTION shop.many(n integer) RETURNS SETOF integer LANGUAGE sql ROWS 10
+                AS $$ SELECT generate_series(1, n) $$;
+            CREATE FUNCTION shop.next(a integer) RETURNS integer LANGUAGE sql IMMUTABLE
+                RETURN a + 1;
+            CREATE FUNCTION shop.double(a integer) RETURNS integer LANGUAGE sql
+                BEGIN ATOMIC SELECT a * 2; END;
+            CREATE FUNCTION shop.rows() RETURNS TABLE(id integer, name text) LANGUAGE plpgsql
+                AS $f$ BEGIN RETURN QUERY SELECT 1, 'a$$b'; END $f$;
+            CREATE PROCEDURE shop.bump(INOUT a integer) LANGUAGE plpgsql
+                AS $$ BEGIN a := a + 1; END $$;
+            CREATE FUNCTION shop.\"Weird Name\"(VARIADIC a integer[]) RETURNS integer
+                LANGUAGE sql AS $$ SELECT 1 $$;",
+        ) else {
+            return;
+        };
+        let files = render_both(&fixture.url);
+        let total = &files[Path::new("functions/shop/total.sql")];
+        assert!(total.contains("CREATE FUNCTION shop.total(a text) RETURNS integer\n"));
+        assert!(total.contains("CREATE FUNCTION shop.total(a integer, b integer DEFAULT 2)"));
+    }
+
+    #[test]
+    fn privileges_match_pg_dump() {
+        let Some(fixture) = Fixture::create(
+            "privileges",
+            "DO $$ BEGIN CREATE ROLE willpg_api;
+                EXCEPTION WHEN duplicate_object OR unique_violation THEN NULL; END $$;
+            DO $$ BEGIN CREATE ROLE \"Willpg Web\";
+                EXCEPTION WHEN duplicate_object OR unique_violation THEN NULL; END $$;
+            CREATE SCHEMA shop;
+            CREATE TABLE shop.\"order\" (id integer, email text);
+            CREATE SEQUENCE shop.ids;
+            CREATE FUNCTION shop.touch() RETURNS integer LANGUAGE sql AS $$ SELECT 1 $$;
+            GRANT USAGE ON SCHEMA shop TO \"Willpg Web\";
+            GRANT CREATE ON SCHEMA shop TO willpg_api;
+            GRANT SELECT, INSERT ON shop.\"order\" TO \"Willpg Web\";
+            GRANT SELECT, INSERT, DELETE, UPDATE ON shop.\"order\" TO willpg_api
+                WITH GRANT OPTION;
+            GRANT SELECT (email), UPDATE (email) ON shop.\"order\" TO \"Willpg Web\";
+            GRANT SELECT, UPDATE ON SEQUENCE shop.ids TO willpg_api;
+            REVOKE ALL ON FUNCTION shop.touch() FROM PUBLIC;
+            GRANT EXECUTE ON FUNCTION shop.touch() TO \"Willpg Web\";
+            ALTER DEFAULT PRIVILEGES REVOKE EXECUTE ON FUNCTIONS FROM PUBLIC;
+            ALTER DEFAULT PRIVILEGES IN SCHEMA shop GRANT SELECT ON TABLES TO \"Willpg Web\";
+            ALTER DEFAULT PRIVILEGES FOR ROLE willpg_api
+                GRANT USAGE ON SEQUENCES TO \"Willpg Web\";",
+        ) else {
+            return;
+        };
+        let files = render_both(&fixture.url);
+        let order = &files[Path::new("tables/shop/order.sql")];
+        assert!(order.contains("GRANT SELECT,INSERT ON TABLE shop.\"order\" TO \"Willpg Web\";"));
+        assert!(files[Path::new("general.sql")].contains("DEFAULT PRIVILEGES FOR SEQUENCES"));
+    }
+
+    #[test]
+    fn tables_match_pg_dump() {
+        let Some(fixture) = Fixture::create(
+            "tables",
+            "CREATE SCHEMA shop;
+            CREATE FUNCTION shop.stamp() RETURNS trigger LANGUAGE plpgsql
+                AS $$ BEGIN NEW.updated_at := now(); RETURN NEW; END $$;
+            CREATE TABLE shop.customer (id bigserial PRIMARY KEY, email text NOT NULL UNIQUE,
+                \"Nick Name\" text DEFAULT 'anon');
+            CREATE TABLE shop.\"order\" (id integer GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+                customer_id bigint REFERENCES shop.customer (id) ON DELETE CASCADE,
+                total numeric(10,2) CHECK (total >= 0), updated_at timestamptz);
+            CREATE INDEX order_customer ON shop.\"order\" (customer_id) WHERE total > 0;
+            CREATE TRIGGER order_stamp BEFORE UPDATE ON shop.\"order\"
+                FOR EACH ROW EXECUTE FUNCTION shop.stamp();
+            CREATE VIEW shop.big_orders AS SELECT id, total FROM shop.\"order\" WHERE total > 100;
+            COMMENT ON TABLE shop.customer IS 'who''s buying';
+            COMMENT ON COLUMN shop.customer.email IS 'unique login';",
+        ) else {
+            return;
+        };
+        let files = render_both(&fixture.url);
+        assert!(files[Path::new("tables/shop/order.sql")].contains("CREATE TRIGGER order_stamp"));
+    }
+
+    #[test]
+    fn test_databases_are_on_the_same_server() {
+        assert_eq!(
+            with_database(
+                "postgresql://postgres@localhost:5432/postgres?host=/tmp",
+                "fx"
+            ),
+            "postgresql://postgres@localhost:5432/fx?host=/tmp"
+        );
+        assert_eq!(
+            with_database("postgres://localhost", "fx"),
+            "postgres://localhost/fx"
+        );
+    }
+}
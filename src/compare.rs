@@ -0,0 +1,561 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde_json::{Value, json};
+use similar::TextDiff;
+
+use crate::diff::{self, Change, quote_ident, quote_literal, statements};
+use crate::model::{Column, Domain, Function, Table, TypeKind, UserType, tables};
+use crate::structs::{ObjectType, Schema, SchemaSection};
+
+// the order categories are reported in
+const CATEGORIES: [&str; 16] = [
+    "schemas",
+    "extensions",
+    "types",
+    "functions",
+    "tables",
+    "columns",
+    "views",
+    "sequences",
+    "constraints",
+    "indexes",
+    "triggers",
+    "policies",
+    "rules",
+    "grants",
+    "comments",
+    "other",
+];
+
+#[derive(Clone, Copy)]
+enum Status {
+    OnlyFrom,
+    OnlyTo,
+    Differs,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::OnlyFrom => "only_from",
+            Status::OnlyTo => "only_to",
+            Status::Differs => "differs",
+        }
+    }
+
+    fn marker(self) -> char {
+        match self {
+            Status::OnlyFrom => '-',
+            Status::OnlyTo => '+',
+            Status::Differs => '~',
+        }
+    }
+}
+
+struct Entry {
+    category: &'static str,
+    object_type: &'static str,
+    object: String,
+    status: Status,
+    // the SQL of the side the object exists on, for objects only on one side
+    definition: Option<String>,
+    // a unified diff of the two definitions, for objects on both sides
+    diff: Option<String>,
+    // the model of a table or function, keyed by what it is
+    model: Option<(&'static str, Value)>,
+    // the attributes of a function that differ, with their value on each side
+    attributes: Vec<(&'static str, String, String)>,
+}
+
+// how two schemas drift apart, grouped by the kind of object
+pub struct Report {
+    from: String,
+    to: String,
+    entries: Vec<Entry>,
+}
+
+impl Report {
+    pub fn new(from_label: String, to_label: String, from: &Schema, to: &Schema) -> Self {
+        let (before, after) = (modeled(from), modeled(to));
+        let mut entries = Vec::new();
+        for change in diff::changes(from, to) {
+            let (status, section) = match change {
+                Change::Added(section) => (Status::OnlyTo, section),
+                Change::Removed(section) => (Status::OnlyFrom, section),
+                Change::Modified { to, .. } => (Status::Differs, to),
+            };
+            let header = &section.header;
+            let key = (header.schema.clone(), table_of(section));
+            let model = match status {
+                Status::OnlyFrom => before.get(&key),
+                Status::OnlyTo | Status::Differs => after.get(&key),
+            };
+            // a default is part of its column, which the column entries compare
+            if header.object_type == ObjectType::Default && model.is_some() {
+                continue;
+            }
+            let (definition, diff) = match change {
+                Change::Added(section) | Change::Removed(section) => {
+                    (Some(statements(section)), None)
+                }
+                Change::Modified { from, to } => {
+                    (None, Some(unified_diff(&statements(from), &statements(to))))
+                }
+            };
+            entries.push(Entry {
+                category: category(header.object_type),
+                object_type: header.object_type.as_str(),
+                object: object_name(section),
+                status,
+                definition,
+                diff,
+                model: match header.object_type {
+                    ObjectType::Table => model.map(|table| ("table", table.to_json())),
+                    ObjectType::Function | ObjectType::Procedure => Function::parse(section)
+                        .ok()
+                        .map(|function| ("function", function.to_json())),
+                    ObjectType::Type | ObjectType::Domain => UserType::parse(section)
+                        .ok()
+                        .map(|user_type| ("user_type", user_type.to_json())),
+                    _ => None,
+                },
+                attributes: match (&change, header.object_type) {
+                    (
+                        Change::Modified { from, to },
+                        ObjectType::Function | ObjectType::Procedure,
+                    ) => function_attributes(from, to),
+                    (Change::Modified { from, to }, ObjectType::Domain) => {
+                        domain_attributes(from, to)
+                    }
+                    _ => Vec::new(),
+                },
+            });
+            if let (Change::Modified { from, to }, ObjectType::Type) = (&change, header.object_type)
+            {
+                entries.extend(value_entries(from, to));
+            }
+        }
+        for (key, table) in &after {
+            if let Some(previous) = before.get(key) {
+                entries.extend(column_entries(previous, table));
+            }
+        }
+        entries.sort_by_key(|entry| CATEGORIES.iter().position(|c| *c == entry.category));
+        Report {
+            from: from_label,
+            to: to_label,
+            entries,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let changes: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let mut value = json!({
+                    "category": entry.category,
+                    "type": entry.object_type,
+                    "object": entry.object,
+                    "status": entry.status.as_str(),
+                });
+                if let Some(definition) = &entry.definition {
+                    value["definition"] = json!(definition);
+                }
+                if let Some(diff) = &entry.diff {
+                    value["diff"] = json!(diff);
+                }
+                if let Some((key, model)) = &entry.model {
+                    value[*key] = model.clone();
+                }
+                if !entry.attributes.is_empty() {
+                    value["attributes"] = entry
+                        .attributes
+                        .iter()
+                        .map(|(name, from, to)| json!({ "name": name, "from": from, "to": to }))
+                        .collect();
+                }
+                value
+            })
+            .collect();
+        json!({ "from": self.from, "to": self.to, "changes": changes })
+    }
+}
+
+// --- from
+// +++ to
+//
+// tables
+//   + shop.coupon
+//   ~ shop.order
+//     @@ -3,5 +3,7 @@
+//     ...
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "--- {}\n+++ {}", self.from, self.to)?;
+        if self.entries.is_empty() {
+            return write!(f, "\nno differences");
+        }
+        let mut category = "";
+        for entry in &self.entries {
+            if entry.category != category {
+                category = entry.category;
+                writeln!(f, "\n{category}")?;
+            }
+            write!(f, "  {} {}", entry.status.marker(), entry.object)?;
+            // a column definition or where an enum value went is one line, short enough to show
+            // in place
+            match (entry.object_type, &entry.definition) {
+                ("COLUMN" | "ENUM VALUE", Some(definition)) => writeln!(f, " {definition}")?,
+                _ => writeln!(f)?,
+            }
+            for (name, from, to) in &entry.attributes {
+                writeln!(f, "    {name}: {from} -> {to}")?;
+            }
+            for line in entry.diff.iter().flat_map(|diff| diff.lines()) {
+                writeln!(f, "    {line}")?;
+            }
+        }
+        let count =
+            |status: fn(Status) -> bool| self.entries.iter().filter(|e| status(e.status)).count();
+        write!(
+            f,
+            "\n{} only in {}, {} only in {}, {} differ",
+            count(|s| matches!(s, Status::OnlyFrom)),
+            self.from,
+            count(|s| matches!(s, Status::OnlyTo)),
+            self.to,
+            count(|s| matches!(s, Status::Differs)),
+        )
+    }
+}
+
+fn category(object_type: ObjectType) -> &'static str {
+    match object_type {
+        ObjectType::Schema => "schemas",
+        ObjectType::Extension => "extensions",
+        ObjectType::Type | ObjectType::Domain | ObjectType::ShellType => "types",
+        ObjectType::Function | ObjectType::Procedure | ObjectType::Aggregate => "functions",
+        ObjectType::Table | ObjectType::ForeignTable | ObjectType::TableAttach => "tables",
+        ObjectType::Default => "columns",
+        ObjectType::View | ObjectType::MaterializedView => "views",
+        ObjectType::Sequence | ObjectType::SequenceOwnedBy => "sequences",
+        ObjectType::Constraint | ObjectType::CheckConstraint | ObjectType::FkConstraint => {
+            "constraints"
+        }
+        ObjectType::Index | ObjectType::IndexAttach | ObjectType::Statistics => "indexes",
+        ObjectType::Trigger | ObjectType::EventTrigger => "triggers",
+        ObjectType::Policy | ObjectType::RowSecurity => "policies",
+        ObjectType::Rule => "rules",
+        ObjectType::Acl | ObjectType::DefaultAcl => "grants",
+        ObjectType::Comment | ObjectType::SecurityLabel => "comments",
+        _ => "other",
+    }
+}
+
+// shop.total(integer), or TABLE shop.customer for the grants and comments on an object
+fn object_name(section: &SchemaSection) -> String {
+    let header = &section.header;
+    let signature = header.signature.as_deref().unwrap_or("");
+    if header.schema == "-" {
+        return format!("{}{signature}", header.name);
+    }
+    match header.object_type {
+        ObjectType::Acl | ObjectType::Comment | ObjectType::SecurityLabel => {
+            match header.target() {
+                Some((kind, name)) => format!("{kind} {}.{name}{signature}", header.schema),
+                None => format!("{}.{}{signature}", header.schema, header.name),
+            }
+        }
+        _ => format!("{}.{}{signature}", header.schema, header.name),
+    }
+}
+
+// only the hunks, the report already says which side is which
+fn unified_diff(from: &str, to: &str) -> String {
+    let (from, to) = (format!("{from}\n"), format!("{to}\n"));
+    TextDiff::from_lines(&from, &to)
+        .unified_diff()
+        .context_radius(3)
+        .iter_hunks()
+        .map(|hunk| hunk.to_string())
+        .collect()
+}
+
+// what differs between two versions of a function other than its body, which the diff shows
+// volatility: IMMUTABLE -> STABLE
+fn function_attributes(
+    from: &SchemaSection,
+    to: &SchemaSection,
+) -> Vec<(&'static str, String, String)> {
+    let (Ok(from), Ok(to)) = (Function::parse(from), Function::parse(to)) else {
+        return Vec::new();
+    };
+    let attributes = |function: &Function| {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let arguments: Vec<String> = function.arguments.iter().map(|a| a.to_string()).collect();
+        let config: Vec<String> = function
+            .config
+            .iter()
+            .map(|(name, value)| format!("{name} TO {value}"))
+            .collect();
+        [
+            ("arguments", arguments.join(", ")),
+            (
+                "returns",
+                function
+                    .returns
+                    .as_ref()
+                    .map(|r| r.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("language", function.language.clone()),
+            ("volatility", function.volatility.as_str().to_string()),
+            ("strict", function.strict.to_string()),
+            ("security definer", function.security_definer.to_string()),
+            ("leakproof", function.leakproof.to_string()),
+            ("window", function.window.to_string()),
+            ("parallel", optional(&function.parallel)),
+            ("cost", optional(&function.cost)),
+            ("rows", optional(&function.rows)),
+            ("support", optional(&function.support)),
+            ("config", config.join("; ")),
+        ]
+    };
+    attributes(&from)
+        .into_iter()
+        .zip(attributes(&to))
+        .filter(|((_, a), (_, b))| a != b)
+        .map(|((name, a), (_, b))| (name, a, b))
+        .collect()
+}
+
+// what differs between two versions of a domain
+// default: ''::text -> -
+fn domain_attributes(
+    from: &SchemaSection,
+    to: &SchemaSection,
+) -> Vec<(&'static str, String, String)> {
+    let domain = |section| match UserType::parse(section).map(|user_type| user_type.kind) {
+        Ok(TypeKind::Domain(domain)) => Some(domain),
+        _ => None,
+    };
+    let (Some(from), Some(to)) = (domain(from), domain(to)) else {
+        return Vec::new();
+    };
+    let attributes = |domain: &Domain| {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let checks: Vec<String> = domain.checks.iter().map(|c| c.to_string()).collect();
+        [
+            ("base type", domain.base_type.clone()),
+            ("collation", optional(&domain.collation)),
+            ("not null", domain.not_null.to_string()),
+            ("default", optional(&domain.default)),
+            ("checks", checks.join("; ")),
+        ]
+    };
+    attributes(&from)
+        .into_iter()
+        .zip(attributes(&to))
+        .filter(|((_, a), (_, b))| a != b)
+        .map(|((name, a), (_, b))| (name, a, b))
+        .collect()
+}
+
+// the labels added to or removed from an enum, with where each new one went
+// + shop.order_status 'refunded' AFTER 'paid'
+fn value_entries(from: &SchemaSection, to: &SchemaSection) -> Vec<Entry> {
+    let (Ok(before), Ok(after)) = (UserType::parse(from), UserType::parse(to)) else {
+        return Vec::new();
+    };
+    let (TypeKind::Enum(old), TypeKind::Enum(new)) = (&before.kind, &after.kind) else {
+        return Vec::new();
+    };
+    let entry = |label: &str, status, definition| Entry {
+        category: "types",
+        object_type: "ENUM VALUE",
+        object: format!("{}.{} {}", after.schema, after.name, quote_literal(label)),
+        status,
+        definition,
+        diff: None,
+        model: None,
+        attributes: Vec::new(),
+    };
+    let mut entries = Vec::new();
+    for (i, label) in new.iter().enumerate() {
+        if old.contains(label) {
+            continue;
+        }
+        let position = match (i.checked_sub(1), new.get(i + 1)) {
+            (Some(previous), _) => Some(format!("AFTER {}", quote_literal(&new[previous]))),
+            (None, Some(next)) => Some(format!("BEFORE {}", quote_literal(next))),
+            (None, None) => None,
+        };
+        entries.push(entry(label, Status::OnlyTo, position));
+    }
+    for label in old {
+        if !new.contains(label) {
+            entries.push(entry(label, Status::OnlyFrom, None));
+        }
+    }
+    entries
+}
+
+// the tables of a schema by schema and name, ordered so the report is too
+fn modeled(schema: &Schema) -> BTreeMap<(String, String), Table> {
+    tables(schema)
+        .into_iter()
+        .map(|table| ((table.schema.clone(), table.name.clone()), table))
+        .collect()
+}
+
+// the table a table or default section belongs to, defaults are named `<table> <column>`
+fn table_of(section: &SchemaSection) -> String {
+    let name = &section.header.name;
+    match section.header.object_type {
+        ObjectType::Default => name.split_whitespace().next().unwrap_or(name).to_string(),
+        _ => name.clone(),
+    }
+}
+
+// the columns that differ between two versions of a table, defaults and identity included
+fn column_entries(from: &Table, to: &Table) -> Vec<Entry> {
+    let entry = |column: &Column, status, definition, diff| Entry {
+        category: "columns",
+        object_type: "COLUMN",
+        object: format!("{}.{}.{}", to.schema, to.name, column.name),
+        status,
+        definition,
+        diff,
+        model: None,
+        attributes: Vec::new(),
+    };
+    // the column line without the name, which the object already says
+    let definition = |column: &Column| {
+        let line = column.to_string();
+        line[quote_ident(&column.name).len()..]
+            .trim_start()
+            .to_string()
+    };
+    let mut entries = Vec::new();
+    for column in &to.columns {
+        match from.column(&column.name) {
+            None => entries.push(entry(
+                column,
+                Status::OnlyTo,
+                Some(definition(column)),
+                None,
+            )),
+            Some(previous) if previous.to_string() != column.to_string() => entries.push(entry(
+                column,
+                Status::Differs,
+                None,
+                Some(format!("-{previous}\n+{column}\n")),
+            )),
+            Some(_) => (),
+        }
+    }
+    for column in &from.columns {
+        if to.column(&column.name).is_none() {
+            entries.push(entry(
+                column,
+                Status::OnlyFrom,
+                Some(definition(column)),
+                None,
+            ));
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(from: &Schema, to: &Schema) -> Report {
+        Report::new("a.sql".to_string(), "b.sql".to_string(), from, to)
+    }
+
+    #[test]
+    fn same_schemas_have_no_differences() {
+        let schema = Schema::from_sections(&[(
+            "status",
+            "TYPE",
+            "CREATE TYPE shop.status AS ENUM (\n    'new'\n);",
+        )]);
+        assert_eq!(
+            report(&schema, &schema).to_string(),
+            "--- a.sql\n+++ b.sql\n\nno differences"
+        );
+    }
+
+    #[test]
+    fn columns_are_compared_one_by_one() {
+        let from = Schema::from_sections(&[(
+            "order",
+            "TABLE",
+            "CREATE TABLE shop.\"order\" (\n    id integer NOT NULL,\n    note text\n);",
+        )]);
+        let to = Schema::from_sections(&[(
+            "order",
+            "TABLE",
+            "CREATE TABLE shop.\"order\" (\n    id bigint NOT NULL,\n    \"from\" text\n);",
+        )]);
+        assert_eq!(
+            report(&from, &to).to_string(),
+            "--- a.sql\n+++ b.sql\n\ntables\n  ~ shop.order\n    @@ -1,4 +1,4 @@\n     \
+             CREATE TABLE shop.\"order\" (\n    -    id integer NOT NULL,\n    -    note text\n    \
+             +    id bigint NOT NULL,\n    +    \"from\" text\n     );\n\ncolumns\n  ~ \
+             shop.order.id\n    -id integer NOT NULL\n    +id bigint NOT NULL\n  + \
+             shop.order.from text\n  - shop.order.note text\n\n1 only in a.sql, 1 only in \
+             b.sql, 2 differ"
+        );
+        let json = report(&from, &to).to_json();
+        assert_eq!(json["changes"][0]["table"]["columns"][1]["name"], "from");
+        assert_eq!(json["changes"][2]["object"], "shop.order.from");
+        assert_eq!(json["changes"][2]["status"], "only_to");
+    }
+
+    #[test]
+    fn functions_and_enums_show_what_changed() {
+        let from = Schema::from_sections(&[
+            (
+                "status",
+                "TYPE",
+                "CREATE TYPE shop.status AS ENUM (\n    'new',\n    'paid'\n);",
+            ),
+            (
+                "total(integer)",
+                "FUNCTION",
+                "CREATE FUNCTION shop.total(a integer) RETURNS integer\n    LANGUAGE sql \
+                 IMMUTABLE\n    RETURN (a + 1);",
+            ),
+        ]);
+        let to = Schema::from_sections(&[
+            (
+                "status",
+                "TYPE",
+                "CREATE TYPE shop.status AS ENUM (\n    'draft',\n    'new'\n);",
+            ),
+            (
+                "total(integer)",
+                "FUNCTION",
+                "CREATE FUNCTION shop.total(a integer) RETURNS integer\n    LANGUAGE sql \
+                 STABLE\n    RETURN (a + 1);",
+            ),
+        ]);
+        let text = report(&from, &to).to_string();
+        assert!(text.contains("\n  + shop.status 'draft' BEFORE 'new'\n  - shop.status 'paid'\n"));
+        assert!(text.contains("\n  ~ shop.total(integer)\n    volatility: IMMUTABLE -> STABLE\n"));
+        let json = report(&from, &to).to_json();
+        let changes = json["changes"].as_array().unwrap();
+        assert_eq!(changes[0]["user_type"]["values"], json!(["draft", "new"]));
+        assert_eq!(changes[1]["type"], "ENUM VALUE");
+        assert_eq!(changes[1]["definition"], "BEFORE 'new'");
+        assert_eq!(
+            changes[3]["attributes"],
+            json!([{ "name": "volatility", "from": "IMMUTABLE", "to": "STABLE" }])
+        );
+        assert_eq!(changes[3]["function"]["body"], "RETURN (a + 1)");
+    }
+}
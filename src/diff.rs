@@ -0,0 +1,857 @@
+use std::collections::HashMap;
+
+use crate::model::{Attribute, Column, Constraint, Domain, Function, Table, TypeKind, UserType};
+use crate::structs::{ObjectType, Schema, SchemaSection, is_link_note};
+
+// sections are the same object in both schemas when all of these match
+type Key<'a> = (ObjectType, &'a str, &'a str, Option<&'a str>);
+
+fn key(section: &SchemaSection) -> Key<'_> {
+    let header = &section.header;
+    (
+        header.object_type,
+        header.schema.as_str(),
+        header.name.as_str(),
+        header.signature.as_deref(),
+    )
+}
+
+pub enum Change<'a> {
+    Added(&'a SchemaSection),
+    Removed(&'a SchemaSection),
+    Modified {
+        from: &'a SchemaSection,
+        to: &'a SchemaSection,
+    },
+}
+
+impl<'a> Change<'a> {
+    // the section as it is after the change, or as it was for a removal
+    pub fn section(&self) -> &'a SchemaSection {
+        match self {
+            Change::Added(section) | Change::Removed(section) => section,
+            Change::Modified { to, .. } => to,
+        }
+    }
+}
+
+// every section that differs between the two schemas, in restore order
+pub fn changes<'a>(from: &'a Schema, to: &'a Schema) -> Vec<Change<'a>> {
+    let before: HashMap<Key, &SchemaSection> = from.sections().map(|s| (key(s), s)).collect();
+    let after: HashMap<Key, &SchemaSection> = to.sections().map(|s| (key(s), s)).collect();
+    let mut changes = Vec::new();
+    for section in to.sections() {
+        match before.get(&key(section)) {
+            None => changes.push(Change::Added(section)),
+            Some(previous) if statements(previous) != statements(section) => {
+                changes.push(Change::Modified {
+                    from: previous,
+                    to: section,
+                })
+            }
+            Some(_) => (),
+        }
+    }
+    for section in from.sections() {
+        if !after.contains_key(&key(section)) {
+            changes.push(Change::Removed(section));
+        }
+    }
+    changes.sort_by(|a, b| {
+        let (a, b) = (a.section(), b.section());
+        let order = |s: &'a SchemaSection| {
+            let (object_type, schema, name, signature) = key(s);
+            (
+                object_type.restore_rank(),
+                schema,
+                name,
+                signature,
+                object_type.as_str(),
+            )
+        };
+        order(a).cmp(&order(b))
+    });
+    changes
+}
+
+// the SQL of a section, so the blank lines around it and our own notes aren't a change
+pub fn statements(section: &SchemaSection) -> String {
+    section
+        .body
+        .trim_matches('\n')
+        .lines()
+        .filter(|line| !is_link_note(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// the statements that take a database from the first schema of the changes to the second. Drops
+// run first, dependents before what they depend on, then everything new or changed is created
+// in restore order
+pub fn migration(changes: &[Change]) -> String {
+    let mut drops = Vec::new();
+    let mut creates = Vec::new();
+    for change in changes {
+        match change {
+            Change::Added(section) => creates.push(annotate(section, statements(section))),
+            Change::Removed(section) => drops.push(annotate(section, drop_statement(section))),
+            Change::Modified { from, to } => match to.header.object_type {
+                ObjectType::Function | ObjectType::Procedure if !replaceable(from, to) => {
+                    drops.push(annotate(from, drop_statement(from)));
+                    creates.push(annotate(to, statements(to)));
+                }
+                ObjectType::Function | ObjectType::Procedure | ObjectType::View => {
+                    creates.push(annotate(to, or_replace(&statements(to))))
+                }
+                // these restate the whole object, the new statement replaces the old
+                ObjectType::Comment
+                | ObjectType::SecurityLabel
+                | ObjectType::Default
+                | ObjectType::RowSecurity
+                | ObjectType::SequenceOwnedBy => creates.push(annotate(to, statements(to))),
+                ObjectType::Acl | ObjectType::DefaultAcl => creates.push(annotate(
+                    to,
+                    format!("{}\n{}", reverse_grants(&statements(from)), statements(to)),
+                )),
+                ObjectType::FkConstraint
+                | ObjectType::Constraint
+                | ObjectType::CheckConstraint
+                | ObjectType::Index
+                | ObjectType::Statistics
+                | ObjectType::Trigger
+                | ObjectType::EventTrigger
+                | ObjectType::Policy
+                | ObjectType::Rule
+                | ObjectType::MaterializedView
+                | ObjectType::Aggregate
+                | ObjectType::Cast
+                | ObjectType::TableAttach => {
+                    drops.push(annotate(from, drop_statement(from)));
+                    creates.push(annotate(to, statements(to)));
+                }
+                ObjectType::Table => match alter_table(from, to) {
+                    Some(sql) => creates.push(annotate(to, sql)),
+                    None => creates.push(annotate(to, manual(to))),
+                },
+                ObjectType::Type | ObjectType::Domain => match alter_type(from, to) {
+                    Some(sql) => creates.push(annotate(to, sql)),
+                    None => creates.push(annotate(to, manual(to))),
+                },
+                _ => creates.push(annotate(to, manual(to))),
+            },
+        }
+    }
+    drops.reverse();
+    let mut migration = String::new();
+    if !drops.is_empty() {
+        migration.push_str(&format!("{}\n", drops.join("\n\n")));
+    }
+    if !creates.is_empty() {
+        if !migration.is_empty() {
+            migration.push('\n');
+        }
+        migration.push_str(&format!("{}\n", creates.join("\n\n")));
+    }
+    migration
+}
+
+// a line in the style of the pg_dump headers naming the object each statement is for
+// -- order_pkey; Type: CONSTRAINT; Schema: shop
+fn annotate(section: &SchemaSection, sql: String) -> String {
+    let header = &section.header;
+    format!(
+        "-- {}{}; Type: {}; Schema: {}\n{sql}",
+        header.name,
+        header.signature.as_deref().unwrap_or(""),
+        header.object_type.as_str(),
+        header.schema
+    )
+}
+
+// a change we can't migrate without losing data or dependents, left for a person to write
+fn manual(section: &SchemaSection) -> String {
+    let commented: Vec<String> = statements(section)
+        .lines()
+        .map(|line| format!("-- {line}"))
+        .collect();
+    format!(
+        "-- changed, no automatic migration, the new definition is:\n{}",
+        commented.join("\n")
+    )
+}
+
+// the ALTER TABLE statements for a table whose columns or inline checks changed, None when
+// something changed that the table model doesn't capture
+fn alter_table(from: &SchemaSection, to: &SchemaSection) -> Option<String> {
+    let (before, after) = (Table::parse(from).ok()?, Table::parse(to).ok()?);
+    if before.unlogged != after.unlogged || before.clauses != after.clauses {
+        return None;
+    }
+    let added = added_statements(
+        from,
+        to,
+        &before.create_statement(),
+        &after.create_statement(),
+    )?;
+
+    let table = after.qualified_name();
+    let mut sql = Vec::new();
+    let same_check =
+        |a: &Constraint, b: &Constraint| a.name == b.name && a.definition == b.definition;
+    for check in &before.checks {
+        if !after.checks.iter().any(|c| same_check(c, check)) {
+            sql.push(format!(
+                "ALTER TABLE {table} DROP CONSTRAINT {};",
+                quote_ident(&check.name)
+            ));
+        }
+    }
+    for column in &before.columns {
+        if after.column(&column.name).is_none() {
+            sql.push(format!(
+                "ALTER TABLE {table} DROP COLUMN {};",
+                quote_ident(&column.name)
+            ));
+        }
+    }
+    for column in &after.columns {
+        match before.column(&column.name) {
+            None => sql.push(format!("ALTER TABLE {table} ADD COLUMN {column};")),
+            Some(previous) => sql.extend(alter_column(&table, previous, column)?),
+        }
+    }
+    for check in &after.checks {
+        if !before.checks.iter().any(|c| same_check(c, check)) {
+            sql.push(format!("ALTER TABLE {table} ADD CONSTRAINT {check};"));
+        }
+    }
+    sql.extend(added);
+    match sql.is_empty() {
+        true => None,
+        false => Some(sql.join("\n")),
+    }
+}
+
+// the ALTER TYPE or ALTER DOMAIN statements for an enum, composite type or domain, None when
+// the change needs the type dropped and created again
+fn alter_type(from: &SchemaSection, to: &SchemaSection) -> Option<String> {
+    let (before, after) = (UserType::parse(from).ok()?, UserType::parse(to).ok()?);
+    let added = added_statements(
+        from,
+        to,
+        &before.create_statement(),
+        &after.create_statement(),
+    )?;
+    let name = after.qualified_name();
+    let mut sql = match (&before.kind, &after.kind) {
+        (TypeKind::Enum(old), TypeKind::Enum(new)) => add_values(&name, old, new)?,
+        (TypeKind::Composite(old), TypeKind::Composite(new)) => alter_attributes(&name, old, new)?,
+        (TypeKind::Domain(old), TypeKind::Domain(new)) => alter_domain(&name, old, new)?,
+        _ => return None,
+    };
+    sql.extend(added);
+    match sql.is_empty() {
+        true => None,
+        false => Some(sql.join("\n")),
+    }
+}
+
+// labels can be added but not removed, renamed or moved. Each new label goes after the one
+// before it, which is already there by then, or before the first old label when it leads
+fn add_values(name: &str, old: &[String], new: &[String]) -> Option<Vec<String>> {
+    let kept: Vec<&String> = new.iter().filter(|label| old.contains(label)).collect();
+    if kept.len() != old.len() || kept.iter().zip(old).any(|(a, b)| *a != b) {
+        return None;
+    }
+    let mut sql = Vec::new();
+    for (i, label) in new.iter().enumerate() {
+        if old.contains(label) {
+            continue;
+        }
+        let position = match (i.checked_sub(1), old.first()) {
+            (Some(previous), _) => format!(" AFTER {}", quote_literal(&new[previous])),
+            (None, Some(first)) => format!(" BEFORE {}", quote_literal(first)),
+            (None, None) => String::new(),
+        };
+        sql.push(format!(
+            "ALTER TYPE {name} ADD VALUE {}{position};",
+            quote_literal(label)
+        ));
+    }
+    Some(sql)
+}
+
+// ADD ATTRIBUTE appends, so new attributes have to come after the old ones
+fn alter_attributes(name: &str, old: &[Attribute], new: &[Attribute]) -> Option<Vec<String>> {
+    let find = |attributes: &'_ [Attribute], name: &str| {
+        attributes
+            .iter()
+            .position(|attribute| attribute.name == name)
+    };
+    let order: Vec<&str> = old
+        .iter()
+        .filter(|a| find(new, &a.name).is_some())
+        .chain(new.iter().filter(|a| find(old, &a.name).is_none()))
+        .map(|a| a.name.as_str())
+        .collect();
+    if !order.iter().eq(new.iter().map(|a| &a.name)) {
+        return None;
+    }
+    let mut sql = Vec::new();
+    for attribute in old {
+        if find(new, &attribute.name).is_none() {
+            sql.push(format!(
+                "ALTER TYPE {name} DROP ATTRIBUTE {};",
+                quote_ident(&attribute.name)
+            ));
+        }
+    }
+    for attribute in new {
+        match find(old, &attribute.name).map(|i| &old[i]) {
+            None => sql.push(format!("ALTER TYPE {name} ADD ATTRIBUTE {attribute};")),
+            Some(previous)
+                if previous.data_type != attribute.data_type
+                    || previous.collation != attribute.collation =>
+            {
+                let collate = match &attribute.collation {
+                    Some(collation) => format!(" COLLATE {collation}"),
+                    None => String::new(),
+                };
+                sql.push(format!(
+                    "ALTER TYPE {name} ALTER ATTRIBUTE {} TYPE {}{collate};",
+                    quote_ident(&attribute.name),
+                    attribute.data_type
+                ));
+            }
+            Some(_) => (),
+        }
+    }
+    Some(sql)
+}
+
+// everything but the base type and collation of a domain can change in place
+fn alter_domain(name: &str, old: &Domain, new: &Domain) -> Option<Vec<String>> {
+    if old.base_type != new.base_type || old.collation != new.collation {
+        return None;
+    }
+    let alter = format!("ALTER DOMAIN {name}");
+    let same_check =
+        |a: &Constraint, b: &Constraint| a.name == b.name && a.definition == b.definition;
+    let mut sql = Vec::new();
+    for check in &old.checks {
+        if !new.checks.iter().any(|c| same_check(c, check)) {
+            sql.push(format!(
+                "{alter} DROP CONSTRAINT {};",
+                quote_ident(&check.name)
+            ));
+        }
+    }
+    if old.default != new.default {
+        match &new.default {
+            Some(default) => sql.push(format!("{alter} SET DEFAULT {default};")),
+            None => sql.push(format!("{alter} DROP DEFAULT;")),
+        }
+    }
+    if old.not_null != new.not_null {
+        match new.not_null {
+            true => sql.push(format!("{alter} SET NOT NULL;")),
+            false => sql.push(format!("{alter} DROP NOT NULL;")),
+        }
+    }
+    for check in &new.checks {
+        if !old.checks.iter().any(|c| same_check(c, check)) {
+            sql.push(format!("{alter} ADD CONSTRAINT {check};"));
+        }
+    }
+    Some(sql)
+}
+
+// a generated column can't be changed in place, everything else can
+fn alter_column(table: &str, from: &Column, to: &Column) -> Option<Vec<String>> {
+    if from.generated != to.generated {
+        return None;
+    }
+    let alter = format!("ALTER TABLE {table} ALTER COLUMN {}", quote_ident(&to.name));
+    let mut sql = Vec::new();
+    if from.data_type != to.data_type || from.collation != to.collation {
+        let collate = match &to.collation {
+            Some(collation) => format!(" COLLATE {collation}"),
+            None => String::new(),
+        };
+        sql.push(format!("{alter} TYPE {}{collate};", to.data_type));
+    }
+    if from.default != to.default {
+        match &to.default {
+            Some(default) => sql.push(format!("{alter} SET DEFAULT {default};")),
+            None => sql.push(format!("{alter} DROP DEFAULT;")),
+        }
+    }
+    if from.not_null != to.not_null {
+        match to.not_null {
+            true => sql.push(format!("{alter} SET NOT NULL;")),
+            false => sql.push(format!("{alter} DROP NOT NULL;")),
+        }
+    }
+    Some(sql)
+}
+
+// the statements that follow the CREATE of the new version of a modeled object and not the old
+// one, e.g. a new comment. None when one that went away can't be undone by running the others,
+// unless it's an owner the new statements replace
+fn added_statements(
+    from: &SchemaSection,
+    to: &SchemaSection,
+    old_create: &str,
+    new_create: &str,
+) -> Option<Vec<String>> {
+    let (old_rest, new_rest) = (
+        other_statements(from, old_create)?,
+        other_statements(to, new_create)?,
+    );
+    let is_owner = |s: &String| s.starts_with("ALTER ") && s.contains(" OWNER TO ");
+    if old_rest
+        .iter()
+        .any(|s| !(new_rest.contains(s) || is_owner(s) && new_rest.iter().any(is_owner)))
+    {
+        return None;
+    }
+    Some(
+        new_rest
+            .into_iter()
+            .filter(|s| !old_rest.contains(s))
+            .collect(),
+    )
+}
+
+// the statements of a section after its CREATE, e.g. the owner. None when the model of the
+// object doesn't give back the CREATE it was read from, so a change to it could go unnoticed
+fn other_statements(section: &SchemaSection, create: &str) -> Option<Vec<String>> {
+    let sql = statements(section);
+    let mut statements = Vec::new();
+    let mut statement = Vec::new();
+    for line in sql.lines() {
+        if statement.is_empty() && line.is_empty() {
+            continue;
+        }
+        statement.push(line);
+        if line.ends_with(';') {
+            statements.push(statement.join("\n"));
+            statement.clear();
+        }
+    }
+    match statements.first() {
+        Some(first) if first == create => Some(statements.split_off(1)),
+        _ => None,
+    }
+}
+
+// whether CREATE OR REPLACE can turn one version of a function into the other. It can't change
+// the return type, the OUT arguments or the names of the arguments, or remove a default. When
+// either side doesn't parse we replace it and let the server say
+fn replaceable(from: &SchemaSection, to: &SchemaSection) -> bool {
+    let (Ok(before), Ok(after)) = (Function::parse(from), Function::parse(to)) else {
+        return true;
+    };
+    before.returns == after.returns
+        && before.arguments.len() == after.arguments.len()
+        && before.arguments.iter().zip(&after.arguments).all(|(a, b)| {
+            a.mode == b.mode
+                && a.name == b.name
+                && a.data_type == b.data_type
+                && (a.default.is_none() || b.default.is_some())
+        })
+}
+
+// CREATE FUNCTION shop.total(a integer) to CREATE OR REPLACE FUNCTION shop.total(a integer)
+fn or_replace(sql: &str) -> String {
+    match sql.strip_prefix("CREATE ") {
+        Some(rest) => format!("CREATE OR REPLACE {rest}"),
+        None => sql.to_string(),
+    }
+}
+
+// the keyword pg_dump creates an object type with, for the ones that are dropped by name
+fn create_keyword(object_type: ObjectType) -> Option<&'static str> {
+    match object_type {
+        ObjectType::Table => Some("TABLE"),
+        ObjectType::ForeignTable => Some("FOREIGN TABLE"),
+        ObjectType::View => Some("VIEW"),
+        ObjectType::MaterializedView => Some("MATERIALIZED VIEW"),
+        ObjectType::Sequence => Some("SEQUENCE"),
+        ObjectType::Type | ObjectType::ShellType => Some("TYPE"),
+        ObjectType::Domain => Some("DOMAIN"),
+        ObjectType::Schema => Some("SCHEMA"),
+        ObjectType::Extension => Some("EXTENSION"),
+        ObjectType::Collation => Some("COLLATION"),
+        ObjectType::Conversion => Some("CONVERSION"),
+        ObjectType::Statistics => Some("STATISTICS"),
+        ObjectType::EventTrigger => Some("EVENT TRIGGER"),
+        ObjectType::TextSearchConfiguration => Some("TEXT SEARCH CONFIGURATION"),
+        ObjectType::TextSearchDictionary => Some("TEXT SEARCH DICTIONARY"),
+        ObjectType::TextSearchParser => Some("TEXT SEARCH PARSER"),
+        ObjectType::TextSearchTemplate => Some("TEXT SEARCH TEMPLATE"),
+        ObjectType::ForeignDataWrapper => Some("FOREIGN DATA WRAPPER"),
+        ObjectType::Server => Some("SERVER"),
+        ObjectType::Publication => Some("PUBLICATION"),
+        ObjectType::Subscription => Some("SUBSCRIPTION"),
+        ObjectType::Index => Some("INDEX"),
+        ObjectType::Function => Some("FUNCTION"),
+        ObjectType::Procedure => Some("PROCEDURE"),
+        ObjectType::Aggregate => Some("AGGREGATE"),
+        _ => None,
+    }
+}
+
+fn drop_statement(section: &SchemaSection) -> String {
+    let header = &section.header;
+    let sql = statements(section);
+    let first_line = sql.lines().next().unwrap_or("");
+    let dropped = match header.object_type {
+        ObjectType::Function | ObjectType::Procedure | ObjectType::Aggregate => {
+            created_name(&sql, header.object_type).map(|name| {
+                let keyword = create_keyword(header.object_type).unwrap_or("FUNCTION");
+                // the header signature holds only the identity arguments, which DROP wants
+                format!(
+                    "DROP {keyword} {name}{};",
+                    header.signature.as_deref().unwrap_or("()")
+                )
+            })
+        }
+        // CREATE INDEX brand_slug_idx ON dirac.brand USING btree (slug);
+        ObjectType::Index => created_name(&sql, header.object_type)
+            .map(|name| format!("DROP INDEX {}.{name};", quote_ident(&header.schema))),
+        // CAST (integer AS text)
+        ObjectType::Cast => Some(format!("DROP {};", header.name)),
+        // ALTER TABLE ONLY dirac.brand
+        //     ADD CONSTRAINT brand_pkey PRIMARY KEY (id);
+        ObjectType::Constraint | ObjectType::FkConstraint | ObjectType::CheckConstraint => sql
+            .split_once(" ADD CONSTRAINT ")
+            .and_then(|(table, rest)| {
+                let table = table.trim().strip_prefix("ALTER TABLE ")?;
+                Some(format!(
+                    "ALTER TABLE {table} DROP CONSTRAINT {};",
+                    qualified_name(rest)
+                ))
+            }),
+        // CREATE TRIGGER brand_slug BEFORE INSERT ON dirac.brand FOR EACH ROW ...
+        ObjectType::Trigger => named_on(&sql, "TRIGGER ", " ON ")
+            .map(|(name, table)| format!("DROP TRIGGER {name} ON {table};")),
+        // CREATE POLICY own_rows ON shop.customer USING (...);
+        ObjectType::Policy => named_on(&sql, "POLICY ", " ON ")
+            .map(|(name, table)| format!("DROP POLICY {name} ON {table};")),
+        // CREATE RULE no_delete AS
+        //     ON DELETE TO shop.paid_orders DO INSTEAD NOTHING;
+        ObjectType::Rule => named_on(&sql, "RULE ", " TO ")
+            .map(|(name, table)| format!("DROP RULE {name} ON {table};")),
+        // ALTER TABLE ONLY shop."order" ALTER COLUMN id SET DEFAULT nextval(...);
+        ObjectType::Default => first_line
+            .split_once(" SET DEFAULT ")
+            .map(|(column, _)| format!("{column} DROP DEFAULT;")),
+        ObjectType::RowSecurity => Some(
+            sql.lines()
+                .filter(|line| line.ends_with(" ROW LEVEL SECURITY;"))
+                .map(|line| {
+                    line.replace(" ENABLE ROW", " DISABLE ROW")
+                        .replace(" FORCE ROW", " NO FORCE ROW")
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        // ALTER SEQUENCE shop.order_id_seq OWNED BY shop."order".id;
+        ObjectType::SequenceOwnedBy => first_line
+            .split_once(" OWNED BY ")
+            .map(|(sequence, _)| format!("{sequence} OWNED BY NONE;")),
+        // ALTER TABLE ONLY shop.measure ATTACH PARTITION shop.measure_2024 FOR VALUES ...
+        ObjectType::TableAttach => {
+            first_line
+                .split_once(" ATTACH PARTITION ")
+                .map(|(table, partition)| {
+                    let table = table.replace("ALTER TABLE ONLY ", "ALTER TABLE ");
+                    format!("{table} DETACH PARTITION {};", qualified_name(partition))
+                })
+        }
+        // COMMENT ON TABLE shop."order" IS 'orders';
+        ObjectType::Comment | ObjectType::SecurityLabel => first_line
+            .split_once(" IS ")
+            .map(|(target, _)| format!("{target} IS NULL;")),
+        ObjectType::Acl | ObjectType::DefaultAcl => Some(reverse_grants(&sql)),
+        object_type => created_name(&sql, object_type).map(|name| {
+            let keyword = create_keyword(object_type).unwrap_or_default();
+            format!("DROP {keyword} {name};")
+        }),
+    };
+    dropped.unwrap_or_else(|| "-- removed, no automatic DROP".to_string())
+}
+
+// the possibly qualified name after `CREATE [..] <keyword> [IF NOT EXISTS]`
+fn created_name(sql: &str, object_type: ObjectType) -> Option<&str> {
+    let keyword = create_keyword(object_type)?;
+    let line = sql.lines().find(|line| line.starts_with("CREATE "))?;
+    let (_, rest) = line.split_once(&format!(" {keyword} "))?;
+    let rest = rest.strip_prefix("IF NOT EXISTS ").unwrap_or(rest);
+    Some(qualified_name(rest))
+}
+
+// `<kind> <name> ... <on> <table>`, for objects that are dropped from the table they are on
+fn named_on<'a>(sql: &'a str, kind: &str, on: &str) -> Option<(&'a str, &'a str)> {
+    let (_, rest) = sql.split_once(kind)?;
+    let name = qualified_name(rest);
+    let (_, table) = rest[name.len()..].split_once(on)?;
+    Some((name, qualified_name(table.trim_start())))
+}
+
+// the leading identifier of `dirac."order" (id bigint ...`, quoted parts may hold anything
+fn qualified_name(s: &str) -> &str {
+    let mut in_quotes = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if !in_quotes && (c.is_whitespace() || c == '(' || c == ';' || c == ',') => {
+                return &s[..i];
+            }
+            _ => (),
+        }
+    }
+    s
+}
+
+// GRANT SELECT ON TABLE shop.customer TO api; becomes a REVOKE and the other way around, with or
+// without an ALTER DEFAULT PRIVILEGES in front
+fn reverse_grants(sql: &str) -> String {
+    sql.lines()
+        .filter_map(|line| {
+            let line = line.trim_end_matches(';');
+            let at = ["GRANT ", "REVOKE "]
+                .iter()
+                .filter_map(|keyword| {
+                    line.match_indices(keyword)
+                        .map(|(at, _)| at)
+                        .find(|at| *at == 0 || line[..*at].ends_with(' '))
+                })
+                .min()?;
+            let (prefix, statement) = line.split_at(at);
+            if let Some(rest) = statement.strip_prefix("GRANT ") {
+                let rest = rest.trim_end_matches(" WITH GRANT OPTION");
+                let (what, grantee) = rest.rsplit_once(" TO ")?;
+                Some(format!("{prefix}REVOKE {what} FROM {grantee};"))
+            } else {
+                let rest = statement.strip_prefix("REVOKE ")?;
+                let rest = rest.strip_prefix("GRANT OPTION FOR ").unwrap_or(rest);
+                let (what, grantee) = rest.rsplit_once(" FROM ")?;
+                Some(format!("{prefix}GRANT {what} TO {grantee};"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// the keywords quote_ident() quotes, every one but the unreserved, as of PostgreSQL 17
+const KEYWORDS: &str = "\
+    all analyse analyze and any array as asc asymmetric authorization between bigint binary bit \
+    boolean both case cast char character check coalesce collate collation column concurrently \
+    constraint create cross current_catalog current_date current_role current_schema current_time \
+    current_timestamp current_user dec decimal default deferrable desc distinct do else end \
+    except exists extract false fetch float for foreign freeze from full grant greatest group \
+    grouping having ilike in initially inner inout int integer intersect interval into is isnull \
+    join json json_array json_arrayagg json_exists json_object json_objectagg json_query \
+    json_scalar json_serialize json_table json_value lateral leading least left like limit \
+    localtime localtimestamp merge_action national natural nchar none normalize not notnull null \
+    nullif numeric offset on only or order out outer overlaps overlay placing position precision \
+    primary real references returning right row select session_user setof similar smallint some \
+    substring symmetric system_user table tablesample then time timestamp to trailing treat trim \
+    true union unique user using values varchar variadic verbose when where window with \
+    xmlattributes xmlconcat xmlelement xmlexists xmlforest xmlnamespaces xmlparse xmlpi xmlroot \
+    xmlserialize xmltable";
+
+// headers carry names unquoted, statements need them the way quote_ident() writes them
+pub fn quote_ident(name: &str) -> String {
+    let plain = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    match plain && !KEYWORDS.split_whitespace().any(|keyword| keyword == name) {
+        true => name.to_string(),
+        false => format!("\"{}\"", name.replace('"', "\"\"")),
+    }
+}
+
+// it's to 'it''s'
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, data_type: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            default: None,
+            generated: None,
+            not_null: false,
+            collation: None,
+            identity: None,
+        }
+    }
+
+    fn labels(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+
+    #[test]
+    fn quote_ident_quotes_like_postgres() {
+        assert_eq!(quote_ident("customer"), "customer");
+        assert_eq!(quote_ident("_tmp2"), "_tmp2");
+        assert_eq!(quote_ident("order"), "\"order\"");
+        assert_eq!(quote_ident("json_table"), "\"json_table\"");
+        assert_eq!(quote_ident("Qty"), "\"Qty\"");
+        assert_eq!(quote_ident("2fa"), "\"2fa\"");
+        assert_eq!(quote_ident("price$"), "\"price$\"");
+        assert_eq!(quote_ident("say \"hi\""), "\"say \"\"hi\"\"\"");
+        // unreserved keywords stay as they are
+        assert_eq!(quote_ident("name"), "name");
+        assert_eq!(quote_ident("comment"), "comment");
+    }
+
+    #[test]
+    fn quote_literal_doubles_quotes() {
+        assert_eq!(quote_literal("it's"), "'it''s'");
+    }
+
+    #[test]
+    fn add_values_places_new_labels() {
+        let sql = add_values(
+            "shop.status",
+            &labels(&["new", "paid"]),
+            &labels(&["draft", "new", "held", "it's paid", "paid", "shipped"]),
+        );
+        assert_eq!(
+            sql.unwrap(),
+            [
+                "ALTER TYPE shop.status ADD VALUE 'draft' BEFORE 'new';",
+                "ALTER TYPE shop.status ADD VALUE 'held' AFTER 'new';",
+                "ALTER TYPE shop.status ADD VALUE 'it''s paid' AFTER 'held';",
+                "ALTER TYPE shop.status ADD VALUE 'shipped' AFTER 'paid';",
+            ]
+        );
+        assert_eq!(
+            add_values("shop.status", &[], &labels(&["new"])).unwrap(),
+            ["ALTER TYPE shop.status ADD VALUE 'new';"]
+        );
+    }
+
+    #[test]
+    fn add_values_refuses_removed_and_moved_labels() {
+        let old = labels(&["new", "paid"]);
+        assert!(add_values("shop.status", &old, &labels(&["new"])).is_none());
+        assert!(add_values("shop.status", &old, &labels(&["paid", "new"])).is_none());
+    }
+
+    #[test]
+    fn alter_column_changes_type_default_and_null() {
+        let from = column("from", "integer");
+        let mut to = column("from", "bigint");
+        to.default = Some("0".to_string());
+        to.not_null = true;
+        assert_eq!(
+            alter_column("shop.\"order\"", &from, &to).unwrap(),
+            [
+                "ALTER TABLE shop.\"order\" ALTER COLUMN \"from\" TYPE bigint;",
+                "ALTER TABLE shop.\"order\" ALTER COLUMN \"from\" SET DEFAULT 0;",
+                "ALTER TABLE shop.\"order\" ALTER COLUMN \"from\" SET NOT NULL;",
+            ]
+        );
+        assert_eq!(
+            alter_column("shop.\"order\"", &to, &from).unwrap(),
+            [
+                "ALTER TABLE shop.\"order\" ALTER COLUMN \"from\" TYPE integer;",
+                "ALTER TABLE shop.\"order\" ALTER COLUMN \"from\" DROP DEFAULT;",
+                "ALTER TABLE shop.\"order\" ALTER COLUMN \"from\" DROP NOT NULL;",
+            ]
+        );
+        let mut collated = column("note", "text");
+        collated.collation = Some("pg_catalog.\"C\"".to_string());
+        assert_eq!(
+            alter_column("shop.\"order\"", &column("note", "text"), &collated).unwrap(),
+            ["ALTER TABLE shop.\"order\" ALTER COLUMN note TYPE text COLLATE pg_catalog.\"C\";"]
+        );
+    }
+
+    #[test]
+    fn alter_column_refuses_generated_changes() {
+        let mut to = column("total", "numeric");
+        to.generated = Some("((qty * price))".to_string());
+        assert!(alter_column("shop.\"order\"", &column("total", "numeric"), &to).is_none());
+    }
+
+    #[test]
+    fn migration_alters_a_changed_table() {
+        let from = Schema::from_sections(&[(
+            "order",
+            "TABLE",
+            "CREATE TABLE shop.\"order\" (\n    id bigint NOT NULL,\n    note text\n);",
+        )]);
+        let to = Schema::from_sections(&[(
+            "order",
+            "TABLE",
+            "CREATE TABLE shop.\"order\" (\n    id bigint NOT NULL,\n    qty integer DEFAULT 1 \
+             NOT NULL\n);",
+        )]);
+        assert_eq!(
+            migration(&changes(&from, &to)),
+            "-- order; Type: TABLE; Schema: shop\n\
+             ALTER TABLE shop.\"order\" DROP COLUMN note;\n\
+             ALTER TABLE shop.\"order\" ADD COLUMN qty integer DEFAULT 1 NOT NULL;\n"
+        );
+    }
+
+    #[test]
+    fn migration_alters_a_changed_enum() {
+        let from = Schema::from_sections(&[(
+            "status",
+            "TYPE",
+            "CREATE TYPE shop.status AS ENUM (\n    'new',\n    'paid'\n);",
+        )]);
+        let to = Schema::from_sections(&[(
+            "status",
+            "TYPE",
+            "CREATE TYPE shop.status AS ENUM (\n    'new',\n    'held',\n    'paid'\n);",
+        )]);
+        assert_eq!(
+            migration(&changes(&from, &to)),
+            "-- status; Type: TYPE; Schema: shop\n\
+             ALTER TYPE shop.status ADD VALUE 'held' AFTER 'new';\n"
+        );
+    }
+
+    #[test]
+    fn migration_replaces_or_recreates_a_changed_function() {
+        let total = |returns: &str, body: &str| {
+            Schema::from_sections(&[(
+                "total(integer)",
+                "FUNCTION",
+                &format!(
+                    "CREATE FUNCTION shop.total(a integer) RETURNS {returns}\n    LANGUAGE sql\n    \
+                     AS $$ SELECT {body} $$;"
+                ),
+            )])
+        };
+        let from = total("integer", "a");
+        assert_eq!(
+            migration(&changes(&from, &total("integer", "a + 1"))),
+            "-- total(integer); Type: FUNCTION; Schema: shop\n\
+             CREATE OR REPLACE FUNCTION shop.total(a integer) RETURNS integer\n    LANGUAGE sql\n    \
+             AS $$ SELECT a + 1 $$;\n"
+        );
+        let migration = migration(&changes(&from, &total("bigint", "a")));
+        assert!(migration.starts_with(
+            "-- total(integer); Type: FUNCTION; Schema: shop\nDROP FUNCTION shop.total(integer);\n"
+        ));
+        assert!(migration.contains("\nCREATE FUNCTION shop.total(a integer) RETURNS bigint\n"));
+    }
+}
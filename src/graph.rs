@@ -0,0 +1,406 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fmt,
+};
+
+use crate::structs::{ObjectType, Schema, SchemaSection};
+
+// why one section has to be created after another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    // the schema the object is created in
+    Schema,
+    // FOREIGN KEY ... REFERENCES the other table
+    ForeignKey,
+    // a column, argument or type built on one of our types or domains
+    UsesType,
+    // a trigger and the function it executes
+    ExecutesFunction,
+    // ALTER SEQUENCE ... OWNED BY a table column
+    OwnedBy,
+    // a default or expression calling nextval on the sequence
+    Nextval,
+    // any other object the statements name, e.g. the table of an index or the sources of a view
+    References,
+    // the Dependencies line pg_dump writes above each section of a verbose dump
+    Dump,
+}
+
+impl EdgeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EdgeKind::Schema => "schema",
+            EdgeKind::ForeignKey => "foreign key",
+            EdgeKind::UsesType => "uses type",
+            EdgeKind::ExecutesFunction => "executes function",
+            EdgeKind::OwnedBy => "owned by",
+            EdgeKind::Nextval => "nextval",
+            EdgeKind::References => "references",
+            EdgeKind::Dump => "pg_dump dependency",
+        }
+    }
+}
+
+// the objects of a schema and what each needs to exist before it can be created
+pub struct DependencyGraph<'a> {
+    sections: Vec<&'a SchemaSection>,
+    // for every section, the sections it depends on
+    edges: Vec<Vec<(usize, EdgeKind)>>,
+}
+
+impl<'a> DependencyGraph<'a> {
+    pub fn new(schema: &'a Schema) -> Self {
+        let sections: Vec<&SchemaSection> = schema.sections().collect();
+        // the objects another section can refer to by their qualified name, overloaded functions
+        // share one
+        let mut named: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+        let mut schemas: HashMap<&str, usize> = HashMap::new();
+        let mut dump_ids: HashMap<u32, usize> = HashMap::new();
+        for (i, section) in sections.iter().enumerate() {
+            let header = &section.header;
+            if is_referable(header.object_type) {
+                named
+                    .entry((header.schema.as_str(), header.name.as_str()))
+                    .or_default()
+                    .push(i);
+            }
+            if header.object_type == ObjectType::Schema {
+                schemas.insert(header.name.as_str(), i);
+            }
+            if let Some(dump_id) = header.dump_id {
+                dump_ids.insert(dump_id, i);
+            }
+        }
+
+        let mut edges = vec![Vec::new(); sections.len()];
+        for (i, section) in sections.iter().enumerate() {
+            let header = &section.header;
+            let mut add = |target: usize, kind: EdgeKind| {
+                if target != i && !edges[i].iter().any(|(t, _)| *t == target) {
+                    edges[i].push((target, kind));
+                }
+            };
+            // the archive knows the real dependencies, what we read from the statements comes
+            // second
+            for id in &header.dependencies {
+                if let Some(target) = dump_ids.get(id) {
+                    add(*target, EdgeKind::Dump);
+                }
+            }
+            if let Some(target) = schemas.get(header.schema.as_str()) {
+                add(*target, EdgeKind::Schema);
+            }
+            for (schema, name) in qualified_names(&section.body) {
+                // the section naming itself, which for a function also names its overloads
+                if (schema.as_str(), name.as_str())
+                    == (header.schema.as_str(), header.name.as_str())
+                {
+                    continue;
+                }
+                let Some(targets) = named.get(&(schema.as_str(), name.as_str())) else {
+                    continue;
+                };
+                for target in targets {
+                    add(*target, edge_kind(section, sections[*target]));
+                }
+            }
+        }
+        DependencyGraph { sections, edges }
+    }
+
+    // every section after the ones it depends on. Whenever there is a choice the section that
+    // comes first in restore order goes first, so the result only departs from the restore
+    // ranks where a dependency requires it
+    pub fn order(&self) -> Result<Vec<&'a SchemaSection>, Cycle<'a>> {
+        let mut waiting_on: Vec<usize> = self.edges.iter().map(Vec::len).collect();
+        let mut dependents = vec![Vec::new(); self.sections.len()];
+        for (i, edges) in self.edges.iter().enumerate() {
+            for (target, _) in edges {
+                dependents[*target].push(i);
+            }
+        }
+        let priority = |i: usize| Reverse((self.sections[i].header.object_type.restore_rank(), i));
+        let mut ready: BinaryHeap<_> = (0..self.sections.len())
+            .filter(|i| waiting_on[*i] == 0)
+            .map(priority)
+            .collect();
+        let mut order = Vec::with_capacity(self.sections.len());
+        while let Some(Reverse((_, i))) = ready.pop() {
+            order.push(self.sections[i]);
+            for dependent in &dependents[i] {
+                waiting_on[*dependent] -= 1;
+                if waiting_on[*dependent] == 0 {
+                    ready.push(priority(*dependent));
+                }
+            }
+        }
+        if order.len() == self.sections.len() {
+            return Ok(order);
+        }
+        // every section left waits on another one that is left, following those from any of
+        // them has to come back around
+        let mut path = vec![waiting_on.iter().position(|n| *n > 0).unwrap_or_default()];
+        loop {
+            let last = path[path.len() - 1];
+            let Some((next, _)) = self.edges[last].iter().find(|(t, _)| waiting_on[*t] > 0) else {
+                unreachable!("a section left unordered waits on another");
+            };
+            if let Some(start) = path.iter().position(|i| i == next) {
+                path.drain(..start);
+                path.push(*next);
+                break;
+            }
+            path.push(*next);
+        }
+        let steps = path
+            .windows(2)
+            .map(|pair| {
+                let kind = self.edges[pair[0]]
+                    .iter()
+                    .find(|(t, _)| *t == pair[1])
+                    .map(|(_, kind)| *kind)
+                    .unwrap_or(EdgeKind::References);
+                (self.sections[pair[0]], kind)
+            })
+            .collect();
+        Err(Cycle {
+            steps,
+            back_to: self.sections[path[0]],
+        })
+    }
+}
+
+// sections that depend on each other in a circle, so there is no order to create them in
+#[derive(Debug)]
+pub struct Cycle<'a> {
+    steps: Vec<(&'a SchemaSection, EdgeKind)>,
+    back_to: &'a SchemaSection,
+}
+
+// TABLE shop.a -[foreign key]-> TABLE shop.b -[uses type]-> TABLE shop.a
+impl fmt::Display for Cycle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sections depend on each other: ")?;
+        for (section, kind) in &self.steps {
+            write!(f, "{} -[{}]-> ", describe(section), kind.as_str())?;
+        }
+        write!(f, "{}", describe(self.back_to))
+    }
+}
+
+fn describe(section: &SchemaSection) -> String {
+    let header = &section.header;
+    format!(
+        "{} {}.{}{}",
+        header.object_type.as_str(),
+        header.schema,
+        header.name,
+        header.signature.as_deref().unwrap_or("")
+    )
+}
+
+// the objects other statements name, tables and views, sequences, types and functions
+fn is_referable(object_type: ObjectType) -> bool {
+    matches!(
+        object_type,
+        ObjectType::Table
+            | ObjectType::ForeignTable
+            | ObjectType::View
+            | ObjectType::MaterializedView
+            | ObjectType::Sequence
+            | ObjectType::Type
+            | ObjectType::Domain
+            | ObjectType::Function
+            | ObjectType::Procedure
+            | ObjectType::Aggregate
+    )
+}
+
+fn edge_kind(from: &SchemaSection, to: &SchemaSection) -> EdgeKind {
+    match (from.header.object_type, to.header.object_type) {
+        (ObjectType::FkConstraint, _) => EdgeKind::ForeignKey,
+        (ObjectType::Trigger, ObjectType::Function) => EdgeKind::ExecutesFunction,
+        (ObjectType::SequenceOwnedBy, _) => EdgeKind::OwnedBy,
+        (_, ObjectType::Sequence) if from.body.contains("nextval(") => EdgeKind::Nextval,
+        (_, ObjectType::Type | ObjectType::Domain) => EdgeKind::UsesType,
+        _ => EdgeKind::References,
+    }
+}
+
+// every `schema.name` pair the statements mention, with quoted identifiers unquoted. String
+// literals are read too, for nextval('shop.order_id_seq'::regclass), but dollar quoted function
+// bodies aren't, the server doesn't need what they name to create the function
+fn qualified_names(sql: &str) -> Vec<(String, String)> {
+    let bytes = sql.as_bytes();
+    let mut names = Vec::new();
+    let mut qualifier: Option<String> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let ident = match bytes[i] {
+            b'"' => {
+                let mut ident = String::new();
+                let mut end = i + 1;
+                while end < bytes.len() {
+                    match (bytes[end], bytes.get(end + 1)) {
+                        // a doubled quote is a quote in the name
+                        (b'"', Some(b'"')) => {
+                            ident.push('"');
+                            end += 2;
+                        }
+                        (b'"', _) => break,
+                        _ => {
+                            let len = sql[end..].chars().next().map_or(1, char::len_utf8);
+                            ident.push_str(&sql[end..end + len]);
+                            end += len;
+                        }
+                    }
+                }
+                i = end + 1;
+                ident
+            }
+            b'$' => {
+                qualifier = None;
+                i = skip_dollar_quote(sql, i);
+                continue;
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' || !b.is_ascii() => {
+                let start = i;
+                while i < bytes.len() && is_ident_byte(bytes[i]) {
+                    i += 1;
+                }
+                sql[start..i].to_string()
+            }
+            _ => {
+                qualifier = None;
+                i += 1;
+                continue;
+            }
+        };
+        if let Some(schema) = qualifier.take() {
+            names.push((schema, ident.clone()));
+        }
+        if bytes.get(i) == Some(&b'.') {
+            qualifier = Some(ident);
+            i += 1;
+        }
+    }
+    names
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || !b.is_ascii()
+}
+
+// past the end of the $tag$...$tag$ string starting at `start`, or past the `$` when it doesn't
+// open one, e.g. the $1 of a parameter
+fn skip_dollar_quote(sql: &str, start: usize) -> usize {
+    let rest = &sql[start + 1..];
+    let tag_len = match rest.find('$') {
+        Some(end)
+            if rest[..end]
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii())
+                && !rest.starts_with(|c: char| c.is_ascii_digit()) =>
+        {
+            end
+        }
+        _ => return start + 1,
+    };
+    let tag = &sql[start..start + tag_len + 2];
+    let body = start + tag.len();
+    match sql[body..].find(tag) {
+        Some(end) => body + end + tag.len(),
+        None => sql.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(order: &[&SchemaSection]) -> Vec<String> {
+        order.iter().map(|section| describe(section)).collect()
+    }
+
+    #[test]
+    fn order_puts_dependencies_first() {
+        let schema = Schema::from_sections(&[
+            (
+                "recent",
+                "VIEW",
+                "CREATE VIEW shop.recent AS\n SELECT id FROM shop.paid;",
+            ),
+            (
+                "paid",
+                "VIEW",
+                "CREATE VIEW shop.paid AS\n SELECT id FROM shop.\"order\" WHERE (status = \
+                 'paid'::shop.status);",
+            ),
+            (
+                "order",
+                "TABLE",
+                "CREATE TABLE shop.\"order\" (\n    id bigint,\n    status shop.status\n);",
+            ),
+            (
+                "status",
+                "TYPE",
+                "CREATE TYPE shop.status AS ENUM (\n    'paid'\n);",
+            ),
+            ("shop", "SCHEMA", "CREATE SCHEMA shop;"),
+        ]);
+        let graph = DependencyGraph::new(&schema);
+        assert_eq!(
+            names(&graph.order().unwrap()),
+            [
+                "SCHEMA -.shop",
+                "TYPE shop.status",
+                "TABLE shop.order",
+                "VIEW shop.paid",
+                "VIEW shop.recent",
+            ]
+        );
+    }
+
+    #[test]
+    fn order_reports_a_cycle() {
+        let schema = Schema::from_sections(&[
+            (
+                "a",
+                "VIEW",
+                "CREATE VIEW shop.a AS\n SELECT id FROM shop.b;",
+            ),
+            (
+                "b",
+                "VIEW",
+                "CREATE VIEW shop.b AS\n SELECT id FROM shop.a;",
+            ),
+            (
+                "c",
+                "VIEW",
+                "CREATE VIEW shop.c AS\n SELECT id FROM shop.a;",
+            ),
+        ]);
+        let cycle = DependencyGraph::new(&schema).order().unwrap_err();
+        assert_eq!(
+            cycle.to_string(),
+            "sections depend on each other: VIEW shop.a -[references]-> VIEW shop.b \
+             -[references]-> VIEW shop.a"
+        );
+    }
+
+    #[test]
+    fn qualified_names_skip_function_bodies() {
+        assert_eq!(
+            qualified_names(
+                "CREATE FUNCTION shop.total(a shop.\"Price\") RETURNS bigint\n    AS $body$ \
+                 SELECT shop.secret() $body$; SELECT nextval('shop.order_id_seq'::regclass);"
+            ),
+            [
+                ("shop".to_string(), "total".to_string()),
+                ("shop".to_string(), "Price".to_string()),
+                ("shop".to_string(), "order_id_seq".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,553 @@
+use std::{
+    error::Error,
+    fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    string::FromUtf8Error,
+    thread,
+};
+
+use crate::output::read_manifest;
+use crate::structs::{TocEntry, is_link_note};
+
+// the first bytes of a custom format archive
+const CUSTOM_ARCHIVE_MAGIC: &[u8] = b"PGDMP";
+// tar archives carry `ustar` at this offset of their first header block
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DumpFormat {
+    Plain,
+    Custom,
+    Directory,
+    Tar,
+}
+
+// where an archive is read from, stdin can only be read once so we hold on to its bytes
+enum ArchiveSource<'a> {
+    Path(&'a Path),
+    Bytes(Vec<u8>),
+}
+
+// the text of a dump along with the TOC of the archive it came from, if any
+pub struct Dump {
+    pub text: String,
+    pub toc: Vec<TocEntry>,
+}
+
+// options forwarded to pg_dump
+#[derive(clap::Args, Debug, Default)]
+pub struct PgDumpOptions {
+    /// Only dump the matching schemas
+    #[arg(short = 'n', long = "schema")]
+    schemas: Vec<String>,
+
+    /// Don't dump the matching schemas
+    #[arg(short = 'N', long = "exclude-schema")]
+    exclude_schemas: Vec<String>,
+
+    /// Only dump the matching tables
+    #[arg(short = 't', long = "table")]
+    tables: Vec<String>,
+
+    /// Don't dump the matching tables
+    #[arg(short = 'T', long = "exclude-table")]
+    exclude_tables: Vec<String>,
+
+    /// Don't dump the matching extensions (pg_dump 17+)
+    #[arg(long = "exclude-extension")]
+    exclude_extensions: Vec<String>,
+
+    /// Skip the ALTER ... OWNER TO statements
+    #[arg(long)]
+    no_owner: bool,
+
+    /// Skip GRANT and REVOKE statements
+    #[arg(long, visible_alias = "no-privileges")]
+    no_acl: bool,
+
+    /// Skip COMMENT statements
+    #[arg(long)]
+    no_comments: bool,
+
+    /// Role to SET ROLE to before dumping
+    #[arg(long)]
+    role: Option<String>,
+
+    /// pg_dump binary to run, for when the server needs a specific version
+    #[arg(long, default_value = "pg_dump")]
+    pg_dump_path: PathBuf,
+}
+
+impl PgDumpOptions {
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (flag, patterns) in [
+            ("--schema", &self.schemas),
+            ("--exclude-schema", &self.exclude_schemas),
+            ("--table", &self.tables),
+            ("--exclude-table", &self.exclude_tables),
+            ("--exclude-extension", &self.exclude_extensions),
+        ] {
+            for pattern in patterns {
+                args.push(format!("{flag}={pattern}"));
+            }
+        }
+        for (flag, set) in [
+            ("--no-owner", self.no_owner),
+            ("--no-acl", self.no_acl),
+            ("--no-comments", self.no_comments),
+        ] {
+            if set {
+                args.push(flag.to_string());
+            }
+        }
+        if let Some(role) = &self.role {
+            args.push(format!("--role={role}"));
+        }
+        args
+    }
+}
+
+// the ways running pg_dump can fail that callers want to tell apart, each maps to its own exit
+// code so wrapper scripts can react to them
+#[derive(Debug)]
+pub enum PgDumpError {
+    NotFound(PathBuf),
+    ConnectionRefused(String),
+    AuthenticationFailed(String),
+    VersionMismatch(String),
+    Failed { status: ExitStatus, stderr: String },
+    Io(io::Error),
+    InvalidOutput(FromUtf8Error),
+}
+
+impl PgDumpError {
+    fn from_stderr(status: ExitStatus, stderr: String) -> Self {
+        if stderr.contains("server version mismatch") {
+            PgDumpError::VersionMismatch(stderr)
+        } else if stderr.contains("authentication failed")
+            || stderr.contains("no password supplied")
+            || (stderr.contains("role \"") && stderr.contains("does not exist"))
+        {
+            PgDumpError::AuthenticationFailed(stderr)
+        } else if stderr.contains("Connection refused")
+            || stderr.contains("Is the server running")
+            || stderr.contains("could not translate host name")
+            || stderr.contains("timeout expired")
+        {
+            PgDumpError::ConnectionRefused(stderr)
+        } else {
+            PgDumpError::Failed { status, stderr }
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            PgDumpError::NotFound(_) => 127,
+            PgDumpError::ConnectionRefused(_) => 3,
+            PgDumpError::AuthenticationFailed(_) => 4,
+            PgDumpError::VersionMismatch(_) => 5,
+            PgDumpError::Failed { .. } | PgDumpError::Io(_) | PgDumpError::InvalidOutput(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for PgDumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgDumpError::NotFound(path) => write!(f, "pg_dump not found at {}", path.display()),
+            PgDumpError::ConnectionRefused(stderr) => {
+                write!(f, "pg_dump could not connect: {}", stderr.trim_end())
+            }
+            PgDumpError::AuthenticationFailed(stderr) => {
+                write!(f, "pg_dump authentication failed: {}", stderr.trim_end())
+            }
+            PgDumpError::VersionMismatch(stderr) => write!(
+                f,
+                "pg_dump is older than the server, pass a matching one with --pg-dump-path: {}",
+                stderr.trim_end()
+            ),
+            PgDumpError::Failed { status, stderr } => {
+                write!(f, "pg_dump failed ({status}): {}", stderr.trim_end())
+            }
+            PgDumpError::Io(e) => write!(f, "couldn't run pg_dump: {e}"),
+            PgDumpError::InvalidOutput(e) => write!(f, "pg_dump output isn't UTF-8: {e}"),
+        }
+    }
+}
+
+impl Error for PgDumpError {}
+
+// failure is decided by the exit status, anything pg_dump says on stderr while succeeding (e.g.
+// server version warnings) is passed through to the user
+pub fn get_dump(db_url: &str, options: &PgDumpOptions) -> Result<String, PgDumpError> {
+    let output = Command::new(&options.pg_dump_path)
+        .arg(db_url)
+        .arg("-s")
+        .args(options.args())
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => PgDumpError::NotFound(options.pg_dump_path.clone()),
+            _ => PgDumpError::Io(e),
+        })?;
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if !output.status.success() {
+        return Err(PgDumpError::from_stderr(output.status, stderr));
+    }
+    eprint!("{stderr}");
+    String::from_utf8(output.stdout).map_err(PgDumpError::InvalidOutput)
+}
+
+fn detect_format(bytes: &[u8]) -> DumpFormat {
+    if bytes.starts_with(CUSTOM_ARCHIVE_MAGIC) {
+        DumpFormat::Custom
+    } else if bytes
+        .get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len())
+        .is_some_and(|magic| magic == TAR_MAGIC)
+    {
+        DumpFormat::Tar
+    } else {
+        DumpFormat::Plain
+    }
+}
+
+// reads a dump from a file, a directory archive or `-` for stdin. Custom, directory and tar
+// archives are turned into text with pg_restore
+pub fn read_dump(input: &Path) -> Result<Dump, Box<dyn Error>> {
+    if input == Path::new("-") {
+        return read_piped_dump(std::io::stdin());
+    }
+    let (format, source) = if input.is_dir() {
+        // a tree we wrote earlier reads back like a plain dump
+        if !input.join("toc.dat").exists() {
+            return Ok(Dump {
+                text: read_tree(input)?,
+                toc: Vec::new(),
+            });
+        }
+        (DumpFormat::Directory, ArchiveSource::Path(input))
+    } else {
+        let mut header = Vec::new();
+        fs::File::open(input)?
+            .take((TAR_MAGIC_OFFSET + TAR_MAGIC.len()) as u64)
+            .read_to_end(&mut header)?;
+        (detect_format(&header), ArchiveSource::Path(input))
+    };
+    dump_text(format, source)
+}
+
+// a dump piped to us, which can only be read once so an archive goes to pg_restore as bytes
+fn read_piped_dump(mut reader: impl Read) -> Result<Dump, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    dump_text(detect_format(&bytes), ArchiveSource::Bytes(bytes))
+}
+
+fn dump_text(format: DumpFormat, source: ArchiveSource) -> Result<Dump, Box<dyn Error>> {
+    match (format, source) {
+        (DumpFormat::Plain, ArchiveSource::Bytes(bytes)) => Ok(Dump {
+            text: String::from_utf8(bytes)?,
+            toc: Vec::new(),
+        }),
+        (DumpFormat::Plain, ArchiveSource::Path(path)) => Ok(Dump {
+            text: fs::read_to_string(path)?,
+            toc: Vec::new(),
+        }),
+        (_, source) => {
+            let listing = pg_restore(&source, &["--schema-only", "--list"])?;
+            let toc = listing
+                .lines()
+                .filter(|line| !line.starts_with(';') && !line.trim().is_empty())
+                .map(|line| line.parse::<TocEntry>())
+                .collect::<Result<Vec<_>, _>>()?;
+            // verbose output keeps the TOC entry ids and dependencies in the section headers
+            let text = pg_restore(&source, &["--schema-only", "--verbose", "--file", "-"])?;
+            Ok(Dump { text, toc })
+        }
+    }
+}
+
+// every file carries the pg_dump header of its sections, so the files one after the other parse
+// like the dump they came from. The prologue sorts first and reads as the preamble. When the
+// tree has a manifest only the files we wrote are read, so tests or notes kept alongside them
+// aren't taken for schema
+fn read_tree(root: &Path) -> Result<String, Box<dyn Error>> {
+    let mut files: Vec<PathBuf> = read_manifest(root)?
+        .into_iter()
+        .map(|fp| root.join(fp))
+        .collect();
+    if files.is_empty() {
+        collect_sql_files(root, &mut files)?;
+    }
+    if files.is_empty() {
+        return Err(format!(
+            "{} is neither a directory archive nor an output tree",
+            root.display()
+        )
+        .into());
+    }
+    files.sort();
+    let mut text = String::new();
+    for fp in files {
+        text.push('\n');
+        // the trigger links are ours and written again on the next run
+        for line in fs::read_to_string(fp)?.lines() {
+            if !is_link_note(line) {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+    Ok(text)
+}
+
+fn collect_sql_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        // staging files and directories of an unfinished write start with a dot
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            collect_sql_files(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension == "sql") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn pg_restore(source: &ArchiveSource, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let mut command = Command::new("pg_restore");
+    command
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = match source {
+        ArchiveSource::Path(path) => command.arg(path).output()?,
+        ArchiveSource::Bytes(bytes) => {
+            let mut child = command.stdin(Stdio::piped()).spawn()?;
+            let mut stdin = child.stdin.take().ok_or("pg_restore stdin unavailable")?;
+            // written from a thread so a full stdout pipe can't deadlock us
+            let bytes = bytes.clone();
+            let writer = thread::spawn(move || stdin.write_all(&bytes));
+            let output = child.wait_with_output()?;
+            writer
+                .join()
+                .map_err(|_| "pg_restore stdin writer panicked")??;
+            output
+        }
+    };
+    if !output.status.success() {
+        return Err(format!(
+            "pg_restore failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::scratch_dir;
+
+    const DUMP: &str = "--\n-- PostgreSQL database dump\n--\n\n\
+        --\n-- Name: customer; Type: TABLE; Schema: shop; Owner: postgres\n--\n\n\
+        CREATE TABLE shop.customer (\n    id integer\n);\n";
+
+    #[test]
+    fn plain_dumps_are_read_from_a_file_or_stdin() {
+        let dir = scratch_dir("plain-dump");
+        let file = dir.join("schema.sql");
+        fs::write(&file, DUMP).unwrap();
+        let dump = read_dump(&file).unwrap();
+        assert_eq!(dump.text, DUMP);
+        assert!(dump.toc.is_empty());
+
+        let dump = read_piped_dump(DUMP.as_bytes()).unwrap();
+        assert_eq!(dump.text, DUMP);
+        assert!(dump.toc.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn output_trees_read_back_as_a_dump() {
+        let dir = scratch_dir("tree");
+        fs::create_dir_all(dir.join("tables/shop")).unwrap();
+        fs::create_dir_all(dir.join(".tree.willpg-new-1")).unwrap();
+        fs::write(
+            dir.join("tables/shop/customer.sql"),
+            "--\n-- Name: customer; Type: TABLE; Schema: shop; Owner: postgres\n--\n\n\
+             -- executes shop.stamp, see functions/shop/stamp.sql\n\
+             CREATE TABLE shop.customer ();\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.sql"), "-- kept by hand\n").unwrap();
+        fs::write(dir.join(".tree.willpg-new-1/half.sql"), "CREATE").unwrap();
+
+        // without a manifest every .sql file but the staging ones is read
+        let text = read_dump(&dir).unwrap().text;
+        assert!(text.contains("-- kept by hand"));
+        assert!(!text.contains("CREATE\n"));
+        assert!(!text.contains("-- executes"));
+        assert!(text.contains("CREATE TABLE shop.customer ();"));
+
+        // with one, only the files it lists
+        fs::write(dir.join(".willpg-manifest"), "tables/shop/customer.sql\n").unwrap();
+        let text = read_dump(&dir).unwrap().text;
+        assert!(!text.contains("-- kept by hand"));
+        assert!(text.contains("CREATE TABLE shop.customer ();"));
+        fs::remove_dir_all(dir).unwrap();
+
+        let empty = scratch_dir("empty-tree");
+        let e = read_dump(&empty).err().unwrap();
+        assert!(
+            e.to_string()
+                .ends_with("is neither a directory archive nor an output tree")
+        );
+        fs::remove_dir_all(empty).unwrap();
+    }
+
+    #[test]
+    fn archive_formats_are_told_by_their_magic() {
+        assert_eq!(detect_format(b"PGDMP\x01\x10\x00"), DumpFormat::Custom);
+        let mut tar = vec![0; 512];
+        tar[..10].copy_from_slice(b"toc.dat\0\0\0");
+        tar[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 6].copy_from_slice(b"ustar\0");
+        assert_eq!(detect_format(&tar), DumpFormat::Tar);
+        assert_eq!(detect_format(DUMP.as_bytes()), DumpFormat::Plain);
+        // too short to hold a tar header, or with the magic anywhere else
+        assert_eq!(detect_format(b""), DumpFormat::Plain);
+        assert_eq!(detect_format(b"ustar"), DumpFormat::Plain);
+        assert_eq!(detect_format(&tar[1..]), DumpFormat::Plain);
+    }
+
+    #[test]
+    fn directories_with_a_toc_are_archives() {
+        let dir = scratch_dir("directory-archive");
+        fs::write(dir.join("schema.sql"), DUMP).unwrap();
+        assert_eq!(read_dump(&dir).unwrap().text, format!("\n{DUMP}"));
+        // now it goes to pg_restore instead of being read as a tree, and this toc.dat is no archive
+        fs::write(dir.join("toc.dat"), "PGDMP").unwrap();
+        assert!(read_dump(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn options_are_passed_on_to_pg_dump() {
+        let options = PgDumpOptions {
+            schemas: vec!["shop".to_string(), "billing".to_string()],
+            exclude_tables: vec!["shop.audit_*".to_string()],
+            no_owner: true,
+            no_acl: true,
+            role: Some("Web".to_string()),
+            ..PgDumpOptions::default()
+        };
+        assert_eq!(
+            options.args(),
+            [
+                "--schema=shop",
+                "--schema=billing",
+                "--exclude-table=shop.audit_*",
+                "--no-owner",
+                "--no-acl",
+                "--role=Web",
+            ]
+        );
+        assert!(PgDumpOptions::default().args().is_empty());
+    }
+
+    // a pg_dump stand-in that prints `stderr` and exits with `code`
+    #[cfg(unix)]
+    fn fake_pg_dump(dir: &Path, stderr: &str, code: i32) -> PgDumpOptions {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = dir.join(format!("pg_dump_{code}"));
+        fs::write(
+            &script,
+            format!("#!/bin/sh\necho '{DUMP}'\necho '{stderr}' >&2\nexit {code}\n"),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        PgDumpOptions {
+            pg_dump_path: script,
+            ..PgDumpOptions::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pg_dump_fails_by_its_exit_status() {
+        let dir = scratch_dir("fake-pg-dump");
+        // a warning alone doesn't fail the dump
+        let options = fake_pg_dump(&dir, "pg_dump: warning: there are circular constraints", 0);
+        assert!(
+            get_dump("postgresql:///shop", &options)
+                .unwrap()
+                .contains("CREATE TABLE")
+        );
+
+        let options = fake_pg_dump(
+            &dir,
+            "pg_dump: error: query failed: ERROR:  permission denied for schema shop",
+            1,
+        );
+        let e = get_dump("postgresql:///shop", &options).unwrap_err();
+        assert!(matches!(e, PgDumpError::Failed { .. }));
+        assert_eq!(e.exit_code(), 1);
+        assert_eq!(
+            e.to_string(),
+            "pg_dump failed (exit status: 1): pg_dump: error: query failed: ERROR:  permission denied for schema shop"
+        );
+
+        let options = PgDumpOptions {
+            pg_dump_path: dir.join("missing"),
+            ..PgDumpOptions::default()
+        };
+        let e = get_dump("postgresql:///shop", &options).unwrap_err();
+        assert!(matches!(e, PgDumpError::NotFound(_)));
+        assert_eq!(e.exit_code(), 127);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pg_dump_errors_are_classified_by_stderr() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let classify = |stderr: &str| {
+            PgDumpError::from_stderr(ExitStatus::from_raw(1 << 8), stderr.to_string())
+        };
+        let e = classify(
+            "pg_dump: error: connection to server on socket \"/tmp/.s.PGSQL.5432\" failed: \
+             Connection refused\n\tIs the server running locally?",
+        );
+        assert!(matches!(e, PgDumpError::ConnectionRefused(_)));
+        assert_eq!(e.exit_code(), 3);
+        let e = classify(
+            "pg_dump: error: connection to server at \"db\" failed: FATAL:  password \
+             authentication failed for user \"api\"",
+        );
+        assert!(matches!(e, PgDumpError::AuthenticationFailed(_)));
+        assert_eq!(e.exit_code(), 4);
+        let e = classify("pg_dump: error: FATAL:  role \"ghost\" does not exist");
+        assert!(matches!(e, PgDumpError::AuthenticationFailed(_)));
+        let e = classify(
+            "pg_dump: error: aborting because of server version mismatch\n\
+             pg_dump: detail: server version: 17.6; pg_dump version: 15.18",
+        );
+        assert!(matches!(e, PgDumpError::VersionMismatch(_)));
+        assert_eq!(e.exit_code(), 5);
+        let e = classify("pg_dump: error: query failed: ERROR:  permission denied");
+        assert!(matches!(e, PgDumpError::Failed { .. }));
+        assert_eq!(e.exit_code(), 1);
+    }
+}
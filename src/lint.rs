@@ -0,0 +1,478 @@
+use std::{error::Error, fmt};
+
+use clap::ValueEnum;
+use serde_json::{Value, json};
+
+use crate::model::{Function, Table, tables};
+use crate::structs::{ObjectType, Schema};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+pub struct Rule {
+    pub id: &'static str,
+    pub severity: Severity,
+    pub description: &'static str,
+}
+
+pub const RULES: [Rule; 7] = [
+    Rule {
+        id: "no-primary-key",
+        severity: Severity::Warning,
+        description: "a table without a primary key",
+    },
+    Rule {
+        id: "unindexed-foreign-key",
+        severity: Severity::Warning,
+        description: "a foreign key no index starts with, deletes on the referenced table scan it",
+    },
+    Rule {
+        id: "timestamp-without-time-zone",
+        severity: Severity::Warning,
+        description: "a timestamp column without time zone",
+    },
+    Rule {
+        id: "character-type",
+        severity: Severity::Info,
+        description: "a character(n) column, padded with spaces to its length",
+    },
+    Rule {
+        id: "definer-without-search-path",
+        severity: Severity::Error,
+        description: "a SECURITY DEFINER function without SET search_path",
+    },
+    Rule {
+        id: "nullable-foreign-key",
+        severity: Severity::Warning,
+        description: "a nullable column of a multi-column foreign key, null rows go unchecked",
+    },
+    Rule {
+        id: "set-null-not-null",
+        severity: Severity::Error,
+        description: "ON DELETE or ON UPDATE SET NULL on a NOT NULL column",
+    },
+];
+
+pub struct Finding {
+    rule: &'static Rule,
+    // shop.order, shop.order.created_at or shop.total(integer)
+    object: String,
+    message: String,
+}
+
+impl Finding {
+    pub fn new(rule: &'static Rule, object: String, message: String) -> Self {
+        Finding {
+            rule,
+            object,
+            message,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "rule": self.rule.id,
+            "severity": self.rule.severity.as_str(),
+            "object": self.object,
+            "message": self.message,
+        })
+    }
+}
+
+// warning[no-primary-key] shop.audit_log: no primary key
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}] {}: {}",
+            self.rule.severity.as_str(),
+            self.rule.id,
+            self.object,
+            self.message
+        )
+    }
+}
+
+// how many findings are at least this severe
+pub fn count<'a>(findings: impl IntoIterator<Item = &'a Finding>, severity: Severity) -> usize {
+    findings
+        .into_iter()
+        .filter(|finding| finding.rule.severity >= severity)
+        .count()
+}
+
+// findings: 1 error, 3 warning, 0 info
+pub fn summary<'a>(findings: impl IntoIterator<Item = &'a Finding>) -> String {
+    let (mut error, mut warning, mut info) = (0, 0, 0);
+    for finding in findings {
+        match finding.rule.severity {
+            Severity::Error => error += 1,
+            Severity::Warning => warning += 1,
+            Severity::Info => info += 1,
+        }
+    }
+    format!("findings: {error} error, {warning} warning, {info} info")
+}
+
+// by object, then rule
+pub fn sort(findings: &mut [Finding]) {
+    findings.sort_by(|a, b| (&a.object, a.rule.id).cmp(&(&b.object, b.rule.id)));
+}
+
+// the check audit shares, a function that runs as its owner should pin its search_path
+pub fn definer_without_search_path(object: String) -> Finding {
+    finding(
+        "definer-without-search-path",
+        object,
+        "runs as its owner with the caller's search_path".to_string(),
+    )
+}
+
+// a rule switched off, everywhere or for one object and what belongs to it
+pub struct Disabled {
+    rule: &'static Rule,
+    object: Option<String>,
+}
+
+impl Disabled {
+    // no-primary-key, or no-primary-key=shop.audit_log for one table
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        let (id, object) = match s.split_once('=') {
+            Some((id, object)) => (id, Some(object.to_string())),
+            None => (s, None),
+        };
+        let rule = rule(id).ok_or_else(|| format!("unknown lint rule {id}"))?;
+        Ok(Disabled { rule, object })
+    }
+}
+
+// what the rules find in a schema, less what was switched off
+pub struct Report {
+    findings: Vec<Finding>,
+    // the tables and functions the model can't read, which no rule looked at, with why
+    unchecked: Vec<(String, String)>,
+}
+
+impl Report {
+    pub fn new(schema: &Schema, disabled: &[Disabled]) -> Self {
+        let mut findings = Vec::new();
+        let mut unchecked = Vec::new();
+        for table in tables(schema) {
+            lint_table(&table, &mut findings);
+        }
+        for section in schema.sections() {
+            let header = &section.header;
+            let object = format!(
+                "{}.{}{}",
+                header.schema,
+                header.name,
+                header.signature.as_deref().unwrap_or("")
+            );
+            let function = match header.object_type {
+                ObjectType::Table => {
+                    if let Err(e) = Table::parse(section) {
+                        unchecked.push((object, e.to_string()));
+                    }
+                    continue;
+                }
+                ObjectType::Function | ObjectType::Procedure => match Function::parse(section) {
+                    Ok(function) => function,
+                    Err(e) => {
+                        unchecked.push((object, e.to_string()));
+                        continue;
+                    }
+                },
+                _ => continue,
+            };
+            if function.security_definer
+                && !function
+                    .config
+                    .iter()
+                    .any(|(name, _)| name == "search_path")
+            {
+                findings.push(definer_without_search_path(object));
+            }
+        }
+        findings.retain(|finding| {
+            !disabled.iter().any(|d| {
+                d.rule.id == finding.rule.id
+                    && d.object
+                        .as_ref()
+                        .is_none_or(|object| belongs_to(&finding.object, object))
+            })
+        });
+        sort(&mut findings);
+        Report {
+            findings,
+            unchecked,
+        }
+    }
+
+    pub fn unchecked(&self) -> &[(String, String)] {
+        &self.unchecked
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        count(&self.findings, severity)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let findings: Vec<Value> = self.findings.iter().map(Finding::to_json).collect();
+        let unchecked: Vec<Value> = self
+            .unchecked
+            .iter()
+            .map(|(object, reason)| json!({ "object": object, "reason": reason }))
+            .collect();
+        json!({ "findings": findings, "unchecked": unchecked })
+    }
+}
+
+// warning[no-primary-key] shop.audit_log: no primary key
+//
+// findings: 1 error, 3 warning, 0 info, 2 objects not checked
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unchecked = match self.unchecked.len() {
+            0 => String::new(),
+            n => format!(", {n} objects not checked"),
+        };
+        if self.findings.is_empty() {
+            return write!(f, "no findings{unchecked}");
+        }
+        for finding in &self.findings {
+            writeln!(f, "{finding}")?;
+        }
+        write!(f, "\n{}{unchecked}", summary(&self.findings))
+    }
+}
+
+fn rule(id: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.id == id)
+}
+
+fn finding(id: &str, object: String, message: String) -> Finding {
+    Finding::new(rule(id).expect("a rule of RULES"), object, message)
+}
+
+// shop.order.created_at belongs to shop.order, as shop.total(integer) does to shop.total
+fn belongs_to(object: &str, owner: &str) -> bool {
+    match object.strip_prefix(owner) {
+        Some(rest) => rest.is_empty() || rest.starts_with(['.', '(']),
+        None => false,
+    }
+}
+
+fn lint_table(table: &Table, findings: &mut Vec<Finding>) {
+    let name = format!("{}.{}", table.schema, table.name);
+    let constraints: Vec<_> = table.checks.iter().chain(&table.constraints).collect();
+    if !constraints
+        .iter()
+        .any(|c| c.definition.starts_with("PRIMARY KEY"))
+    {
+        findings.push(finding(
+            "no-primary-key",
+            name.clone(),
+            "no primary key".to_string(),
+        ));
+    }
+
+    for column in &table.columns {
+        let object = format!("{name}.{}", column.name);
+        if column.data_type.starts_with("timestamp without time zone") {
+            findings.push(finding(
+                "timestamp-without-time-zone",
+                object.clone(),
+                format!("{}, use timestamp with time zone", column.data_type),
+            ));
+        }
+        if column.data_type == "character" || column.data_type.starts_with("character(") {
+            findings.push(finding(
+                "character-type",
+                object,
+                format!("{}, use text or character varying", column.data_type),
+            ));
+        }
+    }
+
+    // the column lists an index is kept for, the ones of primary keys and unique constraints too
+    let indexed: Vec<Vec<String>> = table
+        .indexes
+        .iter()
+        .map(|index| index.leading_columns())
+        .chain(
+            constraints
+                .iter()
+                .filter(|c| !c.definition.starts_with("FOREIGN KEY"))
+                .filter_map(|c| c.columns()),
+        )
+        .collect();
+    for constraint in &constraints {
+        if !constraint.definition.starts_with("FOREIGN KEY") {
+            continue;
+        }
+        let Some(columns) = constraint.columns() else {
+            continue;
+        };
+        let covered = indexed.iter().any(|index| {
+            index.len() >= columns.len()
+                && columns.iter().all(|c| index[..columns.len()].contains(c))
+        });
+        if !covered {
+            findings.push(finding(
+                "unindexed-foreign-key",
+                name.clone(),
+                format!(
+                    "no index starts with ({}) of {}",
+                    columns.join(", "),
+                    constraint.name
+                ),
+            ));
+        }
+        let definition = &constraint.definition;
+        let sets_null = definition.contains(" ON DELETE SET NULL")
+            || definition.contains(" ON UPDATE SET NULL");
+        for column in columns.iter().filter_map(|c| table.column(c)) {
+            let object = format!("{name}.{}", column.name);
+            if sets_null && column.not_null {
+                findings.push(finding(
+                    "set-null-not-null",
+                    object,
+                    format!("NOT NULL but {} sets it to null", constraint.name),
+                ));
+            } else if !column.not_null && columns.len() > 1 && !definition.contains(" MATCH FULL") {
+                findings.push(finding(
+                    "nullable-foreign-key",
+                    object,
+                    format!(
+                        "nullable, {} isn't checked for rows where it is null",
+                        constraint.name
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shop() -> Schema {
+        Schema::from_sections(&[
+            (
+                "customer",
+                "TABLE",
+                "CREATE TABLE shop.customer (\n    id bigint NOT NULL,\n    code character(4)\n);",
+            ),
+            (
+                "order",
+                "TABLE",
+                "CREATE TABLE shop.\"order\" (\n    id bigint NOT NULL,\n    customer_id bigint \
+                 NOT NULL,\n    created_at timestamp without time zone\n);",
+            ),
+            (
+                "customer customer_pkey",
+                "CONSTRAINT",
+                "ALTER TABLE ONLY shop.customer\n    ADD CONSTRAINT customer_pkey PRIMARY KEY (id);",
+            ),
+            (
+                "order order_pkey",
+                "CONSTRAINT",
+                "ALTER TABLE ONLY shop.\"order\"\n    ADD CONSTRAINT order_pkey PRIMARY KEY (id);",
+            ),
+            (
+                "order order_customer_id_fkey",
+                "FK CONSTRAINT",
+                "ALTER TABLE ONLY shop.\"order\"\n    ADD CONSTRAINT order_customer_id_fkey \
+                 FOREIGN KEY (customer_id) REFERENCES shop.customer(id) ON DELETE SET NULL;",
+            ),
+            (
+                "reset(bigint)",
+                "FUNCTION",
+                "CREATE FUNCTION shop.reset(p_id bigint) RETURNS void\n    LANGUAGE sql \
+                 SECURITY DEFINER\n    RETURN NULL::void;",
+            ),
+            (
+                "order_2024",
+                "TABLE",
+                "CREATE TABLE shop.order_2024 OF shop.order_row;",
+            ),
+        ])
+    }
+
+    #[test]
+    fn rules_find_what_they_describe() {
+        let report = Report::new(&shop(), &[]);
+        assert_eq!(
+            report.to_string(),
+            "info[character-type] shop.customer.code: character(4), use text or character \
+             varying\nwarning[unindexed-foreign-key] shop.order: no index starts with \
+             (customer_id) of order_customer_id_fkey\nwarning[timestamp-without-time-zone] \
+             shop.order.created_at: timestamp without time zone, use timestamp with time \
+             zone\nerror[set-null-not-null] shop.order.customer_id: NOT NULL but \
+             order_customer_id_fkey sets it to null\nerror[definer-without-search-path] \
+             shop.reset(bigint): runs as its owner with the caller's search_path\n\nfindings: 2 \
+             error, 2 warning, 1 info, 1 objects not checked"
+        );
+        assert_eq!(report.count(Severity::Error), 2);
+        assert_eq!(report.count(Severity::Warning), 4);
+        assert_eq!(report.count(Severity::Info), 5);
+    }
+
+    #[test]
+    fn objects_the_model_cant_read_are_reported() {
+        let report = Report::new(&shop(), &[]);
+        assert_eq!(report.unchecked().len(), 1);
+        assert_eq!(report.unchecked()[0].0, "shop.order_2024");
+        let json = report.to_json();
+        assert_eq!(json["unchecked"][0]["object"], "shop.order_2024");
+        assert_eq!(json["findings"][4]["rule"], "definer-without-search-path");
+    }
+
+    #[test]
+    fn disabled_rules_are_left_out() {
+        let disabled = [
+            Disabled::parse("character-type").unwrap(),
+            Disabled::parse("set-null-not-null=shop.order").unwrap(),
+            Disabled::parse("timestamp-without-time-zone=shop.customer").unwrap(),
+            Disabled::parse("definer-without-search-path=shop.reset").unwrap(),
+        ];
+        let report = Report::new(&shop(), &disabled);
+        assert_eq!(
+            report.to_string(),
+            "warning[unindexed-foreign-key] shop.order: no index starts with (customer_id) of \
+             order_customer_id_fkey\nwarning[timestamp-without-time-zone] shop.order.created_at: \
+             timestamp without time zone, use timestamp with time zone\n\nfindings: 0 error, 2 \
+             warning, 0 info, 1 objects not checked"
+        );
+    }
+
+    #[test]
+    fn unknown_rules_cant_be_disabled() {
+        let error = Disabled::parse("no-such-rule").err().unwrap();
+        assert_eq!(error.to_string(), "unknown lint rule no-such-rule");
+    }
+
+    #[test]
+    fn objects_belong_to_their_table_or_function() {
+        assert!(belongs_to("shop.order", "shop.order"));
+        assert!(belongs_to("shop.order.created_at", "shop.order"));
+        assert!(belongs_to("shop.total(integer)", "shop.total"));
+        assert!(!belongs_to("shop.orders", "shop.order"));
+        assert!(!belongs_to("shop.order", "shop.order.created_at"));
+    }
+}
@@ -1,35 +1,390 @@
+mod audit;
+#[cfg(feature = "catalog")]
+mod catalog;
+mod compare;
+mod diff;
+mod graph;
+mod input;
+mod lint;
+mod model;
+mod output;
 mod structs;
-use clap::Parser;
-use std::path::PathBuf;
-use std::{error::Error, process::Command};
-use structs::Schema;
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use input::{PgDumpError, PgDumpOptions, get_dump, read_dump};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use structs::{Schema, WriteOptions};
 
 #[derive(Parser)]
 #[command(about = "PostgreSQL schema dump and organize", long_about = None)]
+#[command(group(ArgGroup::new("source").required(true).args(["db_url", "input"])))]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long)]
+    db_url: Option<String>,
+
+    /// Read a dump from a file instead of running pg_dump, `-` reads stdin. Custom, directory and
+    /// tar archives are converted with pg_restore
     #[arg(short, long)]
-    db_url: String,
+    input: Option<PathBuf>,
+
+    #[arg(short, long, required = true)]
+    output_fp: Option<PathBuf>,
+
+    /// How the schema is read from --db-url, the catalog backend ignores the pg_dump options
+    #[arg(long, value_enum, default_value_t = Backend::PgDump)]
+    backend: Backend,
+
+    /// Fail when any section of the dump could not be classified
+    #[arg(long)]
+    strict: bool,
+
+    #[command(flatten)]
+    pg_dump: PgDumpOptions,
+
+    #[command(flatten)]
+    write: WriteOptions,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write the SQL that migrates one schema to another
+    Diff(DiffArgs),
+    /// Report the objects that exist on only one side or differ between two schemas
+    Compare(CompareArgs),
+    /// Recreate a schema from an output directory, through psql or as one script
+    Apply(ApplyArgs),
+    /// Check a schema against rules for common design mistakes
+    Lint(LintArgs),
+    /// Report the privilege escalation risks of a schema: SECURITY DEFINER functions, untrusted
+    /// languages and grants to PUBLIC
+    Audit(AuditArgs),
+}
+
+#[derive(clap::Args)]
+struct DiffArgs {
+    /// The schema as it is, a dump in any format or an output directory
+    from: PathBuf,
+
+    /// The schema as it should be, a dump in any format or an output directory
+    to: PathBuf,
+
+    /// Write the migration to a file instead of stdout
+    #[arg(short, long)]
+    output_fp: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct CompareArgs {
+    /// A database URL, a dump in any format or an output directory
+    from: String,
+
+    /// A database URL, a dump in any format or an output directory
+    to: String,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// How the schema is read from a database URL
+    #[arg(long, value_enum, default_value_t = Backend::PgDump)]
+    backend: Backend,
+
+    #[command(flatten)]
+    pg_dump: PgDumpOptions,
+}
+
+#[derive(clap::Args)]
+struct ApplyArgs {
+    /// An output directory, or a dump in any format
+    input: PathBuf,
 
+    /// Run the script against this database with psql instead of printing it
+    #[arg(short, long, conflicts_with = "output_fp")]
+    db_url: Option<String>,
+
+    /// Write the script to a file instead of stdout
     #[arg(short, long)]
-    output_fp: PathBuf,
+    output_fp: Option<PathBuf>,
+
+    /// psql binary to run
+    #[arg(long, default_value = "psql")]
+    psql_path: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct LintArgs {
+    /// A database URL, a dump in any format or an output directory
+    #[arg(required_unless_present = "list_rules")]
+    source: Option<String>,
+
+    /// Print the findings as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Switch a rule off, everywhere or for one object and what belongs to it, e.g.
+    /// no-primary-key=shop.audit_log
+    #[arg(long, value_name = "RULE[=OBJECT]")]
+    disable: Vec<String>,
+
+    /// Exit with an error when there are findings this severe or worse
+    #[arg(long, value_enum, default_value_t = lint::Severity::Error)]
+    fail_on: lint::Severity,
+
+    /// Print the rules and exit
+    #[arg(long)]
+    list_rules: bool,
+
+    /// Fail when a table or function could not be read, and so was not checked
+    #[arg(long)]
+    strict: bool,
+
+    /// How the schema is read from a database URL
+    #[arg(long, value_enum, default_value_t = Backend::PgDump)]
+    backend: Backend,
+
+    #[command(flatten)]
+    pg_dump: PgDumpOptions,
+}
+
+#[derive(clap::Args)]
+struct AuditArgs {
+    /// A database URL, a dump in any format or an output directory
+    source: String,
+
+    /// A role clients connect as, whose SECURITY DEFINER functions are reported
+    #[arg(long)]
+    api_role: Vec<String>,
+
+    /// Print the findings as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Exit with an error when there are findings this severe or worse
+    #[arg(long, value_enum, default_value_t = lint::Severity::Error)]
+    fail_on: lint::Severity,
+
+    /// How the schema is read from a database URL
+    #[arg(long, value_enum, default_value_t = Backend::PgDump)]
+    backend: Backend,
+
+    #[command(flatten)]
+    pg_dump: PgDumpOptions,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    /// Run pg_dump and parse its output
+    PgDump,
+    /// Query pg_catalog directly, no pg_dump needed
+    Catalog,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let result = match &args.command {
+        Some(Command::Diff(diff_args)) => run_diff(diff_args),
+        Some(Command::Compare(compare_args)) => run_compare(compare_args),
+        Some(Command::Apply(apply_args)) => run_apply(apply_args),
+        Some(Command::Lint(lint_args)) => run_lint(lint_args),
+        Some(Command::Audit(audit_args)) => run_audit(audit_args),
+        None => run(&args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            match e.downcast_ref::<PgDumpError>() {
+                Some(e) => ExitCode::from(e.exit_code()),
+                None => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let output_fp = args.output_fp.as_ref().ok_or("--output-fp is required")?;
+    let schema = match (&args.input, &args.db_url) {
+        (Some(input), _) => read_schema(input)?,
+        (None, Some(db_url)) => read_database(db_url, args.backend, &args.pg_dump)?,
+        (None, None) => unreachable!("clap requires a source"),
+    };
+    report_diagnostics(&schema, args.strict)?;
+    match args.write.sync {
+        true => println!("{}", schema.sync_to_fs(output_fp, &args.write)?),
+        false => schema.write_to_fs(output_fp, &args.write)?,
+    }
+    Ok(())
+}
+
+fn run_diff(args: &DiffArgs) -> Result<(), Box<dyn Error>> {
+    let from = read_schema(&args.from)?;
+    report_diagnostics(&from, false)?;
+    let to = read_schema(&args.to)?;
+    report_diagnostics(&to, false)?;
+    let migration = diff::migration(&diff::changes(&from, &to));
+    match &args.output_fp {
+        Some(output_fp) => fs::write(output_fp, migration)?,
+        None => print!("{migration}"),
+    }
+    Ok(())
+}
+
+fn run_compare(args: &CompareArgs) -> Result<(), Box<dyn Error>> {
+    let mut schemas = Vec::new();
+    for source in [&args.from, &args.to] {
+        schemas.push(read_source(source, args.backend, &args.pg_dump)?);
+    }
+    let report = compare::Report::new(
+        source_label(&args.from),
+        source_label(&args.to),
+        &schemas[0],
+        &schemas[1],
+    );
+    match args.json {
+        true => println!("{:#}", report.to_json()),
+        false => println!("{report}"),
+    }
+    Ok(())
+}
+
+fn run_apply(args: &ApplyArgs) -> Result<(), Box<dyn Error>> {
+    let schema = read_schema(&args.input)?;
+    report_diagnostics(&schema, false)?;
+    let script = schema.restore_script()?;
+    match (&args.db_url, &args.output_fp) {
+        (Some(db_url), _) => output::run_psql(db_url, &script, &args.psql_path)?,
+        (None, Some(output_fp)) => fs::write(output_fp, script)?,
+        (None, None) => print!("{script}"),
+    }
+    Ok(())
+}
+
+fn run_lint(args: &LintArgs) -> Result<(), Box<dyn Error>> {
+    let Some(source) = &args.source else {
+        for rule in &lint::RULES {
+            println!(
+                "{:<28} {:<8} {}",
+                rule.id,
+                rule.severity.as_str(),
+                rule.description
+            );
+        }
+        return Ok(());
+    };
+    let disabled = args
+        .disable
+        .iter()
+        .map(|d| lint::Disabled::parse(d))
+        .collect::<Result<Vec<_>, _>>()?;
+    let schema = read_source(source, args.backend, &args.pg_dump)?;
+    let report = lint::Report::new(&schema, &disabled);
+    for (object, reason) in report.unchecked() {
+        eprintln!("warning: not checked `{object}`: {reason}");
+    }
+    match args.json {
+        true => println!("{:#}", report.to_json()),
+        false => println!("{report}"),
+    }
+    if args.strict && !report.unchecked().is_empty() {
+        return Err(format!("{} objects could not be checked", report.unchecked().len()).into());
+    }
+    match report.count(args.fail_on) {
+        0 => Ok(()),
+        n => Err(format!("{n} findings at {} or above", args.fail_on.as_str()).into()),
+    }
+}
+
+fn run_audit(args: &AuditArgs) -> Result<(), Box<dyn Error>> {
+    let schema = read_source(&args.source, args.backend, &args.pg_dump)?;
+    let report = audit::Report::new(&schema, &args.api_role);
+    match args.json {
+        true => println!("{:#}", report.to_json()),
+        false => println!("{report}"),
+    }
+    match report.count(args.fail_on) {
+        0 => Ok(()),
+        n => Err(format!("{n} findings at {} or above", args.fail_on.as_str()).into()),
+    }
+}
+
+// a database URL, a dump in any format or a tree we wrote
+fn read_source(
+    source: &str,
+    backend: Backend,
+    pg_dump: &PgDumpOptions,
+) -> Result<Schema, Box<dyn Error>> {
+    let schema = match is_db_url(source) {
+        true => read_database(source, backend, pg_dump)?,
+        false => read_schema(Path::new(source))?,
+    };
+    report_diagnostics(&schema, false)?;
+    Ok(schema)
+}
+
+fn read_database(
+    db_url: &str,
+    backend: Backend,
+    pg_dump: &PgDumpOptions,
+) -> Result<Schema, Box<dyn Error>> {
+    match backend {
+        Backend::PgDump => Ok(get_dump(db_url, pg_dump)?.parse::<Schema>()?),
+        Backend::Catalog => read_catalog(db_url),
+    }
+}
+
+fn is_db_url(source: &str) -> bool {
+    source.starts_with("postgres://") || source.starts_with("postgresql://")
 }
 
-fn get_dump(db_url: &str) -> Result<String, Box<dyn Error>> {
-    let output = Command::new("pg_dump").arg(db_url).arg("-s").output()?;
-    if !output.stderr.is_empty() {
+// how a source is named in a report, without the password of a database URL
+// postgres://app:secret@db/shop -> postgres://app@db/shop
+fn source_label(source: &str) -> String {
+    let Some((scheme, rest)) = source.split_once("://") else {
+        return source.to_string();
+    };
+    match rest.split_once('@') {
+        Some((userinfo, host)) if userinfo.contains(':') && !userinfo.contains('/') => {
+            let user = userinfo.split(':').next().unwrap_or_default();
+            format!("{scheme}://{user}@{host}")
+        }
+        _ => source.to_string(),
+    }
+}
+
+// a dump in any format, or a tree we wrote
+fn read_schema(input: &Path) -> Result<Schema, Box<dyn Error>> {
+    let dump = read_dump(input)?;
+    let mut schema = dump.text.parse::<Schema>()?;
+    schema.apply_toc(&dump.toc);
+    Ok(schema)
+}
+
+fn report_diagnostics(schema: &Schema, strict: bool) -> Result<(), Box<dyn Error>> {
+    for diagnostic in schema.diagnostics() {
+        eprintln!("warning: {diagnostic}");
+    }
+    if strict && !schema.diagnostics().is_empty() {
         return Err(format!(
-            "pg_dump failed: {}",
-            String::from_utf8_lossy(&output.stderr)
+            "{} sections could not be classified",
+            schema.diagnostics().len()
         )
         .into());
     }
-    Ok(String::from_utf8(output.stdout)?)
+    Ok(())
 }
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
 
-    let output = get_dump(&args.db_url)?;
-    let schema = output.parse::<Schema>()?;
-    schema.write_to_fs(&args.output_fp)?;
-    Ok(())
+#[cfg(feature = "catalog")]
+fn read_catalog(db_url: &str) -> Result<Schema, Box<dyn Error>> {
+    catalog::get_schema(db_url)
+}
+
+#[cfg(not(feature = "catalog"))]
+fn read_catalog(_db_url: &str) -> Result<Schema, Box<dyn Error>> {
+    Err("built without the catalog feature, rebuild with --features catalog".into())
 }
@@ -0,0 +1,1285 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use serde_json::{Value, json};
+
+use crate::diff::{quote_ident, quote_literal};
+use crate::structs::{ObjectType, Schema, SchemaSection};
+
+// a table as its CREATE TABLE statement and the sections pg_dump writes apart from it describe it
+pub struct Table {
+    pub schema: String,
+    pub name: String,
+    pub unlogged: bool,
+    pub columns: Vec<Column>,
+    // the CONSTRAINT lines of the CREATE TABLE, pg_dump writes CHECK constraints inline when it can
+    pub checks: Vec<Constraint>,
+    // what follows the column list, e.g. PARTITION BY RANGE (taken_at) or WITH (fillfactor='70')
+    pub clauses: Vec<String>,
+    // the constraints and indexes of their own sections, only filled in by `tables`
+    pub constraints: Vec<Constraint>,
+    pub indexes: Vec<Index>,
+}
+
+pub struct Column {
+    pub name: String,
+    // as pg_dump formats it, e.g. character varying(255), public.vector(768) or integer[]
+    pub data_type: String,
+    pub default: Option<String>,
+    // the expression of a GENERATED ALWAYS AS (...) STORED column
+    pub generated: Option<String>,
+    pub not_null: bool,
+    pub collation: Option<String>,
+    // dumped with the sequence behind it, only filled in by `tables`
+    pub identity: Option<Identity>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Identity {
+    Always,
+    ByDefault,
+}
+
+pub struct Constraint {
+    pub name: String,
+    // everything after the name, e.g. PRIMARY KEY (id) or CHECK ((qty > 0))
+    pub definition: String,
+}
+
+pub struct Index {
+    pub name: String,
+    pub unique: bool,
+    // the CREATE INDEX statement
+    pub definition: String,
+}
+
+impl Table {
+    // the CREATE TABLE of a table section, failing for the forms we don't model (typed tables,
+    // PARTITION OF)
+    // CREATE TABLE shop."order" (
+    //     id bigint NOT NULL,
+    //     status shop.order_status DEFAULT 'new'::shop.order_status NOT NULL,
+    //     CONSTRAINT order_note_len CHECK ((length(note) < 500))
+    // )
+    // PARTITION BY RANGE (id);
+    pub fn parse(section: &SchemaSection) -> Result<Table, Box<dyn Error>> {
+        let mut lines = section.body.lines();
+        let create = lines
+            .find(|line| line.starts_with("CREATE "))
+            .ok_or("no CREATE TABLE statement")?;
+        let (unlogged, rest) = match create.strip_prefix("CREATE UNLOGGED TABLE ") {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                create
+                    .strip_prefix("CREATE TABLE ")
+                    .ok_or("not a CREATE TABLE statement")?,
+            ),
+        };
+        let (schema, name, rest) = parse_qualified(rest).ok_or("no table name")?;
+        if rest != " (" {
+            return Err(format!("unsupported CREATE TABLE form `{create}`").into());
+        }
+
+        let mut table = Table {
+            schema,
+            name,
+            unlogged,
+            columns: Vec::new(),
+            checks: Vec::new(),
+            clauses: Vec::new(),
+            constraints: Vec::new(),
+            indexes: Vec::new(),
+        };
+        let mut closed = false;
+        for line in lines.by_ref() {
+            if let Some(end) = line.strip_prefix(')') {
+                closed = end == ";";
+                break;
+            }
+            let line = line.trim().trim_end_matches(',');
+            match line.strip_prefix("CONSTRAINT ") {
+                Some(constraint) => table.checks.push(parse_constraint(constraint)?),
+                None => table.columns.push(parse_column(line)?),
+            }
+        }
+        while !closed {
+            let line = lines.next().ok_or("unterminated CREATE TABLE")?;
+            closed = line.ends_with(';');
+            table.clauses.push(line.trim_end_matches(';').to_string());
+        }
+        Ok(table)
+    }
+
+    pub fn qualified_name(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.name))
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    fn column_mut(&mut self, name: &str) -> Option<&mut Column> {
+        self.columns.iter_mut().find(|column| column.name == name)
+    }
+
+    // the statement pg_dump writes for the table, which for a table read by `parse` is the one
+    // it was read from
+    pub fn create_statement(&self) -> String {
+        let mut lines: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| format!("    {column}"))
+            .chain(
+                self.checks
+                    .iter()
+                    .map(|check| format!("    CONSTRAINT {check}")),
+            )
+            .collect();
+        let last = lines.len().saturating_sub(1);
+        for line in &mut lines[..last] {
+            line.push(',');
+        }
+        let mut statement = format!(
+            "CREATE {}TABLE {} (\n{}{})",
+            if self.unlogged { "UNLOGGED " } else { "" },
+            self.qualified_name(),
+            lines.join("\n"),
+            if lines.is_empty() { "" } else { "\n" }
+        );
+        for clause in &self.clauses {
+            statement.push('\n');
+            statement.push_str(clause);
+        }
+        statement.push(';');
+        statement
+    }
+
+    pub fn to_json(&self) -> Value {
+        let constraints = |constraints: &[Constraint]| -> Vec<Value> {
+            constraints
+                .iter()
+                .map(|c| json!({ "name": c.name, "definition": c.definition }))
+                .collect()
+        };
+        let columns: Vec<Value> = self
+            .columns
+            .iter()
+            .map(|column| {
+                json!({
+                    "name": column.name,
+                    "type": column.data_type,
+                    "not_null": column.not_null,
+                    "default": column.default,
+                    "generated": column.generated,
+                    "collation": column.collation,
+                    "identity": column.identity.map(|identity| match identity {
+                        Identity::Always => "always",
+                        Identity::ByDefault => "by default",
+                    }),
+                })
+            })
+            .collect();
+        let indexes: Vec<Value> = self
+            .indexes
+            .iter()
+            .map(|i| json!({ "name": i.name, "unique": i.unique, "definition": i.definition }))
+            .collect();
+        json!({
+            "schema": self.schema,
+            "name": self.name,
+            "unlogged": self.unlogged,
+            "columns": columns,
+            "checks": constraints(&self.checks),
+            "clauses": self.clauses,
+            "constraints": constraints(&self.constraints),
+            "indexes": indexes,
+        })
+    }
+}
+
+// the column line of a CREATE TABLE, with the identity pg_dump leaves to the sequence appended
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", quote_ident(&self.name), self.data_type)?;
+        match (&self.generated, &self.default) {
+            (Some(generated), _) => write!(f, " GENERATED ALWAYS AS {generated} STORED")?,
+            (None, Some(default)) => write!(f, " DEFAULT {default}")?,
+            (None, None) => (),
+        }
+        if self.not_null {
+            write!(f, " NOT NULL")?;
+        }
+        if let Some(collation) = &self.collation {
+            write!(f, " COLLATE {collation}")?;
+        }
+        match self.identity {
+            Some(Identity::Always) => write!(f, " GENERATED ALWAYS AS IDENTITY"),
+            Some(Identity::ByDefault) => write!(f, " GENERATED BY DEFAULT AS IDENTITY"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Constraint {
+    // the columns of a PRIMARY KEY, UNIQUE or FOREIGN KEY constraint, None for the others
+    // FOREIGN KEY (customer_id) REFERENCES shop.customer(id) ON DELETE SET NULL
+    pub fn columns(&self) -> Option<Vec<String>> {
+        let rest = [
+            "PRIMARY KEY (",
+            "UNIQUE (",
+            "UNIQUE NULLS NOT DISTINCT (",
+            "FOREIGN KEY (",
+        ]
+        .iter()
+        .find_map(|prefix| self.definition.strip_prefix(prefix))?;
+        let end = closing_paren(rest)?;
+        split_list(&rest[..end])
+            .into_iter()
+            .map(|column| parse_ident(column).map(|(name, _)| name))
+            .collect()
+    }
+}
+
+impl Index {
+    // the plain columns the index starts with, up to the first expression
+    // CREATE INDEX order_customer ON shop."order" USING btree (customer_id, lower(ref))
+    pub fn leading_columns(&self) -> Vec<String> {
+        let Some((_, rest)) = self.definition.split_once(" USING ") else {
+            return Vec::new();
+        };
+        let Some(rest) = rest.split_once('(').map(|(_, rest)| rest) else {
+            return Vec::new();
+        };
+        let end = closing_paren(rest).unwrap_or(rest.len());
+        split_list(&rest[..end])
+            .into_iter()
+            .map_while(|item| match parse_ident(item) {
+                // an operator class or sort order can follow the column, a bracket makes it a
+                // function call
+                Some((name, rest)) if rest.is_empty() || rest.starts_with(' ') => Some(name),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", quote_ident(&self.name), self.definition)
+    }
+}
+
+// every table of the schema we can model, with its defaults, identity columns, constraints and
+// indexes taken from their own sections
+pub fn tables(schema: &Schema) -> Vec<Table> {
+    let mut tables: Vec<Table> = schema
+        .sections()
+        .filter(|section| section.header.object_type == ObjectType::Table)
+        .filter_map(|section| Table::parse(section).ok())
+        .collect();
+    let mut by_name: HashMap<(String, String), usize> = HashMap::new();
+    for (i, table) in tables.iter().enumerate() {
+        by_name.insert((table.schema.clone(), table.name.clone()), i);
+    }
+    for section in schema.sections() {
+        let Some((schema, name, rest)) = table_statement(section) else {
+            continue;
+        };
+        let Some(table) = by_name.get(&(schema, name)).map(|i| &mut tables[*i]) else {
+            continue;
+        };
+        match section.header.object_type {
+            ObjectType::Default => {
+                if let Some((column, default)) = parse_default(rest)
+                    && let Some(column) = table.column_mut(&column)
+                {
+                    column.default = Some(default);
+                }
+            }
+            ObjectType::Sequence => {
+                if let Some((column, identity)) = parse_identity(rest)
+                    && let Some(column) = table.column_mut(&column)
+                {
+                    column.identity = Some(identity);
+                }
+            }
+            ObjectType::Constraint | ObjectType::CheckConstraint | ObjectType::FkConstraint => {
+                if let Some(constraint) = rest
+                    .trim_start()
+                    .strip_prefix("ADD CONSTRAINT ")
+                    .and_then(|c| parse_constraint(c).ok())
+                {
+                    table.constraints.push(constraint);
+                }
+            }
+            ObjectType::Index => {
+                if let Some(index) = parse_index(section) {
+                    table.indexes.push(index);
+                }
+            }
+            _ => (),
+        }
+    }
+    tables
+}
+
+// the table a dependent section's statement is about and the rest of that statement
+// ALTER TABLE ONLY shop."order" ALTER COLUMN id SET DEFAULT ...
+// CREATE UNIQUE INDEX order_ref ON shop."order" USING btree (ref);
+fn table_statement(section: &SchemaSection) -> Option<(String, String, &str)> {
+    let statement = section.body.trim_start_matches('\n');
+    let rest = match section.header.object_type {
+        ObjectType::Index => statement.split_once(" ON ")?.1,
+        // the identity of a column is the first statement after its CREATE SEQUENCE
+        ObjectType::Sequence => statement.split_once("ALTER TABLE ")?.1,
+        _ => statement.strip_prefix("ALTER TABLE ")?,
+    };
+    parse_qualified(rest.strip_prefix("ONLY ").unwrap_or(rest))
+}
+
+// ALTER COLUMN status SET DEFAULT 'new'::shop.order_status;
+fn parse_default(rest: &str) -> Option<(String, String)> {
+    let (column, rest) = parse_ident(rest.trim_start().strip_prefix("ALTER COLUMN ")?)?;
+    let default = rest.strip_prefix(" SET DEFAULT ")?;
+    Some((column, default.trim_end().trim_end_matches(';').to_string()))
+}
+
+// ALTER COLUMN id ADD GENERATED ALWAYS AS IDENTITY (
+fn parse_identity(rest: &str) -> Option<(String, Identity)> {
+    let (column, rest) = parse_ident(rest.trim_start().strip_prefix("ALTER COLUMN ")?)?;
+    match rest.strip_prefix(" ADD GENERATED ")? {
+        r if r.starts_with("ALWAYS AS IDENTITY") => Some((column, Identity::Always)),
+        r if r.starts_with("BY DEFAULT AS IDENTITY") => Some((column, Identity::ByDefault)),
+        _ => None,
+    }
+}
+
+fn parse_index(section: &SchemaSection) -> Option<Index> {
+    let definition = section
+        .body
+        .lines()
+        .find(|line| line.starts_with("CREATE "))?;
+    let (unique, rest) = match definition.strip_prefix("CREATE UNIQUE INDEX ") {
+        Some(rest) => (true, rest),
+        None => (false, definition.strip_prefix("CREATE INDEX ")?),
+    };
+    let (name, _) = parse_ident(rest)?;
+    Some(Index {
+        name,
+        unique,
+        definition: definition.to_string(),
+    })
+}
+
+// order_note_len CHECK ((length(note) < 500))
+fn parse_constraint(s: &str) -> Result<Constraint, Box<dyn Error>> {
+    let (name, definition) = parse_ident(s).ok_or("no constraint name")?;
+    Ok(Constraint {
+        name,
+        definition: definition.trim().trim_end_matches(';').to_string(),
+    })
+}
+
+// status shop.order_status DEFAULT 'new'::shop.order_status NOT NULL COLLATE pg_catalog."C"
+// pg_dump writes the type, then the default or generation expression, NOT NULL and the
+// collation, so the line is taken apart from the end
+fn parse_column(line: &str) -> Result<Column, Box<dyn Error>> {
+    let (name, rest) = parse_ident(line).ok_or_else(|| format!("no column name in `{line}`"))?;
+    let mut rest = rest.trim_start();
+    let mut collation = None;
+    if let Some((before, after)) = rest.rsplit_once(" COLLATE ")
+        && !after.contains(' ')
+    {
+        collation = Some(after.to_string());
+        rest = before;
+    }
+    let not_null = match rest.strip_suffix(" NOT NULL") {
+        Some(before) => {
+            rest = before;
+            true
+        }
+        None => false,
+    };
+    let mut generated = None;
+    let mut default = None;
+    if let Some((before, after)) = rest.split_once(" GENERATED ALWAYS AS ")
+        && let Some(expression) = after.strip_suffix(" STORED")
+    {
+        generated = Some(expression.to_string());
+        rest = before;
+    } else if let Some((before, after)) = rest.split_once(" DEFAULT ") {
+        default = Some(after.to_string());
+        rest = before;
+    }
+    Ok(Column {
+        name,
+        data_type: rest.to_string(),
+        default,
+        generated,
+        not_null,
+        collation,
+        identity: None,
+    })
+}
+
+// a function or procedure as its CREATE statement describes it
+pub struct Function {
+    pub schema: String,
+    pub name: String,
+    pub procedure: bool,
+    pub arguments: Vec<Argument>,
+    // None for a procedure
+    pub returns: Option<Returns>,
+    pub language: String,
+    pub window: bool,
+    pub volatility: Volatility,
+    pub strict: bool,
+    pub security_definer: bool,
+    pub leakproof: bool,
+    pub cost: Option<String>,
+    pub rows: Option<String>,
+    pub support: Option<String>,
+    pub parallel: Option<String>,
+    // SET search_path TO 'shop', 'pg_temp' is ("search_path", "'shop', 'pg_temp'")
+    pub config: Vec<(String, String)>,
+    // what follows AS, e.g. $$ SELECT a $$, or the BEGIN ATOMIC ... END or RETURN (a + 1) of an
+    // SQL standard body
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Argument {
+    pub mode: ArgumentMode,
+    pub name: Option<String>,
+    pub data_type: String,
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentMode {
+    In,
+    Out,
+    InOut,
+    Variadic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Returns {
+    Type(String),
+    SetOf(String),
+    Table(Vec<Argument>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Volatility {
+    Volatile,
+    Stable,
+    Immutable,
+}
+
+impl Volatility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Volatility::Volatile => "VOLATILE",
+            Volatility::Stable => "STABLE",
+            Volatility::Immutable => "IMMUTABLE",
+        }
+    }
+}
+
+impl ArgumentMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ArgumentMode::In => "IN",
+            ArgumentMode::Out => "OUT",
+            ArgumentMode::InOut => "INOUT",
+            ArgumentMode::Variadic => "VARIADIC",
+        }
+    }
+}
+
+impl Function {
+    // the CREATE FUNCTION or CREATE PROCEDURE of a section, failing for anything the model
+    // wouldn't give back unchanged
+    // CREATE FUNCTION shop.safe_reset(p_id bigint) RETURNS SETOF shop."order"
+    //     LANGUAGE sql STABLE STRICT SECURITY DEFINER
+    //     SET search_path TO 'shop', 'pg_temp'
+    //     AS $$ SELECT * FROM shop."order" WHERE id = p_id $$;
+    pub fn parse(section: &SchemaSection) -> Result<Function, Box<dyn Error>> {
+        let sql = section.body.trim_start_matches('\n');
+        let (procedure, rest) = match sql.strip_prefix("CREATE PROCEDURE ") {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                sql.strip_prefix("CREATE FUNCTION ")
+                    .ok_or("not a CREATE FUNCTION statement")?,
+            ),
+        };
+        let (schema, name, rest) = parse_qualified(rest).ok_or("no function name")?;
+        let rest = rest.strip_prefix('(').ok_or("no argument list")?;
+        let close = closing_paren(rest).ok_or("unterminated argument list")?;
+        let arguments = split_list(&rest[..close])
+            .into_iter()
+            .map(parse_argument)
+            .collect::<Result<Vec<_>, _>>()?;
+        let (header, mut rest) = rest[close + 1..]
+            .split_once('\n')
+            .ok_or("no function attributes")?;
+        let returns = match header.strip_prefix(" RETURNS ") {
+            Some(returns) => Some(parse_returns(returns)?),
+            None if header.is_empty() => None,
+            None => return Err(format!("unexpected `{header}` after the arguments").into()),
+        };
+
+        let mut function = Function {
+            schema,
+            name,
+            procedure,
+            arguments,
+            returns,
+            language: String::new(),
+            window: false,
+            volatility: Volatility::Volatile,
+            strict: false,
+            security_definer: false,
+            leakproof: false,
+            cost: None,
+            rows: None,
+            support: None,
+            parallel: None,
+            config: Vec::new(),
+            body: String::new(),
+        };
+        loop {
+            let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+            if let Some(attributes) = line.strip_prefix("    LANGUAGE ") {
+                function.read_attributes(attributes)?;
+            } else if let Some(setting) = line.strip_prefix("    SET ") {
+                let (name, value) = setting.split_once(" TO ").ok_or("SET without TO")?;
+                function.config.push((name.to_string(), value.to_string()));
+            } else if let Some(body) = rest.strip_prefix("    AS ") {
+                function.body = body[..body_end(body)?].to_string();
+                break;
+            } else if let Some(body) = rest
+                .strip_prefix("    ")
+                .filter(|body| body.starts_with("BEGIN ATOMIC"))
+            {
+                let end = body.find("\nEND;").ok_or("unterminated BEGIN ATOMIC")?;
+                function.body = body[..end + 4].to_string();
+                break;
+            } else if let Some(body) = rest
+                .strip_prefix("    ")
+                .filter(|body| body.starts_with("RETURN "))
+            {
+                function.body = body[..body_end(body)?].to_string();
+                break;
+            } else {
+                return Err(format!("unexpected `{line}` in a function").into());
+            }
+            rest = next;
+        }
+        if !sql.starts_with(&function.create_statement()) {
+            return Err("the function model doesn't give back its statement".into());
+        }
+        Ok(function)
+    }
+
+    // LANGUAGE sql IMMUTABLE STRICT SECURITY DEFINER LEAKPROOF COST 5 PARALLEL SAFE
+    fn read_attributes(&mut self, attributes: &str) -> Result<(), Box<dyn Error>> {
+        let mut words = attributes.split(' ');
+        self.language = words.next().ok_or("no language")?.to_string();
+        while let Some(word) = words.next() {
+            let mut value = || words.next().map(str::to_string).ok_or("missing value");
+            match word {
+                "WINDOW" => self.window = true,
+                "IMMUTABLE" => self.volatility = Volatility::Immutable,
+                "STABLE" => self.volatility = Volatility::Stable,
+                "STRICT" => self.strict = true,
+                "SECURITY" if value()? == "DEFINER" => self.security_definer = true,
+                "LEAKPROOF" => self.leakproof = true,
+                "COST" => self.cost = Some(value()?),
+                "ROWS" => self.rows = Some(value()?),
+                "SUPPORT" => self.support = Some(value()?),
+                "PARALLEL" => self.parallel = Some(value()?),
+                word => return Err(format!("unsupported function attribute {word}").into()),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_statement(&self) -> String {
+        let arguments: Vec<String> = self
+            .arguments
+            .iter()
+            .map(|argument| match (self.procedure, argument.mode) {
+                // pg_dump spells out IN for procedures only
+                (true, ArgumentMode::In) => format!("IN {argument}"),
+                _ => argument.to_string(),
+            })
+            .collect();
+        let mut sql = format!(
+            "CREATE {} {}.{}({})",
+            if self.procedure {
+                "PROCEDURE"
+            } else {
+                "FUNCTION"
+            },
+            quote_ident(&self.schema),
+            quote_ident(&self.name),
+            arguments.join(", ")
+        );
+        if let Some(returns) = &self.returns {
+            sql.push_str(&format!(" RETURNS {returns}"));
+        }
+        sql.push_str(&format!("\n    LANGUAGE {}", self.language));
+        if self.window {
+            sql.push_str(" WINDOW");
+        }
+        if self.volatility != Volatility::Volatile {
+            sql.push_str(&format!(" {}", self.volatility.as_str()));
+        }
+        if self.strict {
+            sql.push_str(" STRICT");
+        }
+        if self.security_definer {
+            sql.push_str(" SECURITY DEFINER");
+        }
+        if self.leakproof {
+            sql.push_str(" LEAKPROOF");
+        }
+        for (keyword, value) in [
+            ("COST", &self.cost),
+            ("ROWS", &self.rows),
+            ("SUPPORT", &self.support),
+            ("PARALLEL", &self.parallel),
+        ] {
+            if let Some(value) = value {
+                sql.push_str(&format!(" {keyword} {value}"));
+            }
+        }
+        for (name, value) in &self.config {
+            sql.push_str(&format!("\n    SET {name} TO {value}"));
+        }
+        match self.body.starts_with("BEGIN ATOMIC") || self.body.starts_with("RETURN ") {
+            true => sql.push_str(&format!("\n    {};", self.body)),
+            false => sql.push_str(&format!("\n    AS {};", self.body)),
+        }
+        sql
+    }
+
+    pub fn to_json(&self) -> Value {
+        let arguments = |arguments: &[Argument]| -> Vec<Value> {
+            arguments
+                .iter()
+                .map(|argument| {
+                    json!({
+                        "mode": argument.mode.as_str(),
+                        "name": argument.name,
+                        "type": argument.data_type,
+                        "default": argument.default,
+                    })
+                })
+                .collect()
+        };
+        let returns = match &self.returns {
+            None => Value::Null,
+            Some(Returns::Type(data_type)) => json!({ "type": data_type }),
+            Some(Returns::SetOf(data_type)) => json!({ "setof": data_type }),
+            Some(Returns::Table(columns)) => json!({ "table": arguments(columns) }),
+        };
+        json!({
+            "schema": self.schema,
+            "name": self.name,
+            "kind": if self.procedure { "procedure" } else { "function" },
+            "arguments": arguments(&self.arguments),
+            "returns": returns,
+            "language": self.language,
+            "window": self.window,
+            "volatility": self.volatility.as_str(),
+            "strict": self.strict,
+            "security_definer": self.security_definer,
+            "leakproof": self.leakproof,
+            "cost": self.cost,
+            "rows": self.rows,
+            "support": self.support,
+            "parallel": self.parallel,
+            "config": self.config.iter().map(|(name, value)| json!({ "name": name, "value": value })).collect::<Vec<_>>(),
+            "body": self.body,
+        })
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.mode != ArgumentMode::In {
+            write!(f, "{} ", self.mode.as_str())?;
+        }
+        if let Some(name) = &self.name {
+            write!(f, "{} ", quote_ident(name))?;
+        }
+        write!(f, "{}", self.data_type)?;
+        match &self.default {
+            Some(default) => write!(f, " DEFAULT {default}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Returns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Returns::Type(data_type) => write!(f, "{data_type}"),
+            Returns::SetOf(data_type) => write!(f, "SETOF {data_type}"),
+            Returns::Table(columns) => {
+                let columns: Vec<String> = columns.iter().map(Argument::to_string).collect();
+                write!(f, "TABLE({})", columns.join(", "))
+            }
+        }
+    }
+}
+
+// the argument types of a function as ACL and COMMENT headers name it, to match the signature
+// in the header of the function
+// (p_id bigint, VARIADIC p_tags text[]) to (bigint, text[])
+pub fn signature_types(signature: &str) -> Option<String> {
+    let arguments = signature.strip_prefix('(')?.strip_suffix(')')?;
+    let types = split_list(arguments)
+        .into_iter()
+        .map(|argument| parse_argument(argument).ok())
+        .collect::<Option<Vec<_>>>()?;
+    let types: Vec<&str> = types
+        .iter()
+        .filter(|argument| argument.mode != ArgumentMode::Out)
+        .map(|argument| argument.data_type.as_str())
+        .collect();
+    Some(format!("({})", types.join(", ")))
+}
+
+// the types pg_dump writes in more than one word, whose first word is no argument name
+const MULTI_WORD_TYPES: [&str; 6] = [
+    "bit",
+    "character",
+    "double",
+    "interval",
+    "time",
+    "timestamp",
+];
+
+// [mode] [name] type [DEFAULT expression]
+fn parse_argument(text: &str) -> Result<Argument, Box<dyn Error>> {
+    let mut mode = ArgumentMode::In;
+    let mut rest = text;
+    for candidate in [
+        ArgumentMode::In,
+        ArgumentMode::Out,
+        ArgumentMode::InOut,
+        ArgumentMode::Variadic,
+    ] {
+        if let Some(after) = rest.strip_prefix(&format!("{} ", candidate.as_str())) {
+            mode = candidate;
+            rest = after;
+            break;
+        }
+    }
+    let (rest, default) = match rest.split_once(" DEFAULT ") {
+        Some((rest, default)) => (rest, Some(default.to_string())),
+        None => (rest, None),
+    };
+    let (name, data_type) = match parse_ident(rest) {
+        Some((name, data_type))
+            if data_type.starts_with(' ')
+                && !(rest.starts_with(|c: char| c != '"')
+                    && MULTI_WORD_TYPES.contains(&name.as_str())) =>
+        {
+            (Some(name), data_type.trim_start())
+        }
+        _ => (None, rest),
+    };
+    if data_type.is_empty() {
+        return Err(format!("no type in the argument `{text}`").into());
+    }
+    Ok(Argument {
+        mode,
+        name,
+        data_type: data_type.to_string(),
+        default,
+    })
+}
+
+// SETOF shop."order", TABLE(id bigint, name text) or a single type
+fn parse_returns(text: &str) -> Result<Returns, Box<dyn Error>> {
+    if let Some(data_type) = text.strip_prefix("SETOF ") {
+        return Ok(Returns::SetOf(data_type.to_string()));
+    }
+    match text
+        .strip_prefix("TABLE(")
+        .and_then(|columns| columns.strip_suffix(')'))
+    {
+        Some(columns) => Ok(Returns::Table(
+            split_list(columns)
+                .into_iter()
+                .map(parse_argument)
+                .collect::<Result<_, _>>()?,
+        )),
+        None => Ok(Returns::Type(text.to_string())),
+    }
+}
+
+// where the string after AS ends, before its semicolon: a dollar quoted body, or the quoted
+// strings of a C function
+fn body_end(body: &str) -> Result<usize, Box<dyn Error>> {
+    if let Some(rest) = body.strip_prefix('$') {
+        let tag_end = rest.find('$').ok_or("unterminated dollar quote")? + 2;
+        let tag = &body[..tag_end];
+        let close = body[tag_end..]
+            .find(tag)
+            .ok_or("unterminated dollar quote")?;
+        return Ok(tag_end + close + tag.len());
+    }
+    let mut quoted = false;
+    for (i, c) in body.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            ';' if !quoted => return Ok(i),
+            _ => (),
+        }
+    }
+    Err("no end to the function body".into())
+}
+
+// the index of the bracket that closes one already opened, skipping quoted text
+fn closing_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return Some(i),
+            (None, ')') => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+// a comma separated list, leaving the commas inside brackets and quotes alone
+fn split_list(s: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                items.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if !s[start..].trim().is_empty() {
+        items.push(s[start..].trim());
+    }
+    items
+}
+
+// a type of our own as its CREATE TYPE or CREATE DOMAIN statement describes it
+pub struct UserType {
+    pub schema: String,
+    pub name: String,
+    pub kind: TypeKind,
+}
+
+pub enum TypeKind {
+    // the labels in their sort order
+    Enum(Vec<String>),
+    Composite(Vec<Attribute>),
+    Domain(Domain),
+}
+
+// an attribute of a composite type
+pub struct Attribute {
+    pub name: String,
+    pub data_type: String,
+    pub collation: Option<String>,
+}
+
+pub struct Domain {
+    pub base_type: String,
+    pub collation: Option<String>,
+    pub not_null: bool,
+    pub default: Option<String>,
+    // the CHECK constraints pg_dump writes inline, NOT VALID ones get a section of their own
+    pub checks: Vec<Constraint>,
+}
+
+impl UserType {
+    // the CREATE TYPE of an enum or composite type section or the CREATE DOMAIN of a domain,
+    // failing for the other kinds of type (range, base, shell)
+    // CREATE TYPE shop.order_status AS ENUM (
+    //     'new',
+    //     'paid'
+    // );
+    // CREATE TYPE shop.price AS (
+    // 	amount numeric(19,4),
+    // 	currency character(3)
+    // );
+    // CREATE DOMAIN shop.email AS text NOT NULL DEFAULT ''::text
+    // 	CONSTRAINT email_check CHECK ((VALUE ~ '@'::text));
+    pub fn parse(section: &SchemaSection) -> Result<UserType, Box<dyn Error>> {
+        let sql = section.body.trim_start_matches('\n');
+        let (domain, rest) = match sql.strip_prefix("CREATE DOMAIN ") {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                sql.strip_prefix("CREATE TYPE ")
+                    .ok_or("not a CREATE TYPE statement")?,
+            ),
+        };
+        let (schema, name, rest) = parse_qualified(rest).ok_or("no type name")?;
+        let mut lines = rest.lines();
+        let first = lines.next().unwrap_or("");
+        let kind = match (domain, first) {
+            (true, _) => {
+                let definition = first.strip_prefix(" AS ").ok_or("no domain base type")?;
+                let mut closed = definition.ends_with(';');
+                // the default comes last and is the only part that could hold the others' words
+                let (definition, default) =
+                    match definition.trim_end_matches(';').split_once(" DEFAULT ") {
+                        Some((definition, default)) => (definition, Some(default.to_string())),
+                        None => (definition.trim_end_matches(';'), None),
+                    };
+                let (definition, not_null) = match definition.strip_suffix(" NOT NULL") {
+                    Some(definition) => (definition, true),
+                    None => (definition, false),
+                };
+                let (base_type, collation) = match definition.split_once(" COLLATE ") {
+                    Some((base_type, collation)) => (base_type, Some(collation.to_string())),
+                    None => (definition, None),
+                };
+                let mut checks = Vec::new();
+                while !closed {
+                    let line = lines.next().ok_or("unterminated CREATE DOMAIN")?;
+                    closed = line.ends_with(';');
+                    let check = line
+                        .trim_end_matches(';')
+                        .strip_prefix("\tCONSTRAINT ")
+                        .ok_or("unexpected line in a domain")?;
+                    checks.push(parse_constraint(check)?);
+                }
+                TypeKind::Domain(Domain {
+                    base_type: base_type.to_string(),
+                    collation,
+                    not_null,
+                    default,
+                    checks,
+                })
+            }
+            (false, " AS ENUM (") => TypeKind::Enum(
+                lines
+                    .take_while(|line| *line != ");")
+                    .map(|line| parse_literal(line.trim().trim_end_matches(',')))
+                    .collect::<Option<_>>()
+                    .ok_or("an enum label that isn't a string")?,
+            ),
+            (false, " AS (") => TypeKind::Composite(
+                lines
+                    .take_while(|line| *line != ");")
+                    .map(|line| parse_attribute(line.trim().trim_end_matches(',')))
+                    .collect::<Result<_, _>>()?,
+            ),
+            (false, _) => return Err(format!("unsupported CREATE TYPE form `{first}`").into()),
+        };
+        let user_type = UserType { schema, name, kind };
+        if !sql.starts_with(&user_type.create_statement()) {
+            return Err("the type model doesn't give back its statement".into());
+        }
+        Ok(user_type)
+    }
+
+    pub fn qualified_name(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.name))
+    }
+
+    // the statement pg_dump writes for the type
+    pub fn create_statement(&self) -> String {
+        let name = self.qualified_name();
+        match &self.kind {
+            TypeKind::Enum(labels) => {
+                let labels: Vec<String> = labels
+                    .iter()
+                    .map(|label| format!("\n    {}", quote_literal(label)))
+                    .collect();
+                format!("CREATE TYPE {name} AS ENUM ({}\n);", labels.join(","))
+            }
+            TypeKind::Composite(attributes) => {
+                let attributes: Vec<String> = attributes
+                    .iter()
+                    .map(|attribute| format!("\n\t{attribute}"))
+                    .collect();
+                format!("CREATE TYPE {name} AS ({}\n);", attributes.join(","))
+            }
+            TypeKind::Domain(domain) => {
+                let mut sql = format!("CREATE DOMAIN {name} AS {}", domain.base_type);
+                if let Some(collation) = &domain.collation {
+                    sql.push_str(&format!(" COLLATE {collation}"));
+                }
+                if domain.not_null {
+                    sql.push_str(" NOT NULL");
+                }
+                if let Some(default) = &domain.default {
+                    sql.push_str(&format!(" DEFAULT {default}"));
+                }
+                for check in &domain.checks {
+                    sql.push_str(&format!("\n\tCONSTRAINT {check}"));
+                }
+                sql.push(';');
+                sql
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({ "schema": self.schema, "name": self.name });
+        match &self.kind {
+            TypeKind::Enum(labels) => {
+                value["kind"] = json!("enum");
+                value["values"] = json!(labels);
+            }
+            TypeKind::Composite(attributes) => {
+                value["kind"] = json!("composite");
+                value["attributes"] = attributes
+                    .iter()
+                    .map(|a| json!({ "name": a.name, "type": a.data_type, "collation": a.collation }))
+                    .collect();
+            }
+            TypeKind::Domain(domain) => {
+                value["kind"] = json!("domain");
+                value["base_type"] = json!(domain.base_type);
+                value["collation"] = json!(domain.collation);
+                value["not_null"] = json!(domain.not_null);
+                value["default"] = json!(domain.default);
+                value["checks"] = domain
+                    .checks
+                    .iter()
+                    .map(|c| json!({ "name": c.name, "definition": c.definition }))
+                    .collect();
+            }
+        }
+        value
+    }
+}
+
+// the attribute line of a CREATE TYPE ... AS (
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", quote_ident(&self.name), self.data_type)?;
+        match &self.collation {
+            Some(collation) => write!(f, " COLLATE {collation}"),
+            None => Ok(()),
+        }
+    }
+}
+
+// amount numeric(19,4) COLLATE pg_catalog."C"
+fn parse_attribute(line: &str) -> Result<Attribute, Box<dyn Error>> {
+    let (name, rest) = parse_ident(line).ok_or("no attribute name")?;
+    let rest = rest.strip_prefix(' ').ok_or("no attribute type")?;
+    let (data_type, collation) = match rest.split_once(" COLLATE ") {
+        Some((data_type, collation)) => (data_type, Some(collation.to_string())),
+        None => (rest, None),
+    };
+    Ok(Attribute {
+        name,
+        data_type: data_type.to_string(),
+        collation,
+    })
+}
+
+// 'it''s' to it's
+fn parse_literal(s: &str) -> Option<String> {
+    let inner = s.strip_prefix('\'')?.strip_suffix('\'')?;
+    Some(inner.replace("''", "'"))
+}
+
+// an identifier at the start of `s`, unquoted, and what follows it
+fn parse_ident(s: &str) -> Option<(String, &str)> {
+    match s.strip_prefix('"') {
+        Some(quoted) => {
+            let mut name = String::new();
+            let mut chars = quoted.char_indices();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' if quoted[i + 1..].starts_with('"') => {
+                        name.push('"');
+                        chars.next();
+                    }
+                    '"' => return Some((name, &quoted[i + 1..])),
+                    c => name.push(c),
+                }
+            }
+            None
+        }
+        None => {
+            let end = s
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(s.len());
+            match end {
+                0 => None,
+                end => Some((s[..end].to_string(), &s[end..])),
+            }
+        }
+    }
+}
+
+// shop."order" at the start of `s`
+fn parse_qualified(s: &str) -> Option<(String, String, &str)> {
+    let (schema, rest) = parse_ident(s)?;
+    let (name, rest) = parse_ident(rest.strip_prefix('.')?)?;
+    Some((schema, name, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_function(signature: &str, sql: &str) -> Function {
+        let schema = Schema::from_sections(&[(signature, "FUNCTION", sql)]);
+        let function = Function::parse(schema.sections().next().unwrap()).unwrap();
+        assert_eq!(function.create_statement(), sql);
+        function
+    }
+
+    fn parse_type(name: &str, object_type: &str, sql: &str) -> UserType {
+        let schema = Schema::from_sections(&[(name, object_type, sql)]);
+        let user_type = UserType::parse(schema.sections().next().unwrap()).unwrap();
+        assert_eq!(user_type.create_statement(), sql);
+        user_type
+    }
+
+    #[test]
+    fn table_round_trips_quoted_names() {
+        let sql = "CREATE TABLE shop.\"order\" (\n    id bigint NOT NULL,\n    \"from\" text \
+                   DEFAULT 'x'::text,\n    \"Qty\" integer NOT NULL,\n    CONSTRAINT order_qty \
+                   CHECK ((\"Qty\" > 0))\n);";
+        let schema = Schema::from_sections(&[("order", "TABLE", sql)]);
+        let table = Table::parse(schema.sections().next().unwrap()).unwrap();
+        assert_eq!(table.create_statement(), sql);
+        assert_eq!(table.name, "order");
+        assert_eq!(table.columns[1].name, "from");
+        assert_eq!(table.columns[1].default.as_deref(), Some("'x'::text"));
+        assert!(table.column("Qty").unwrap().not_null);
+        assert_eq!(table.checks[0].name, "order_qty");
+    }
+
+    #[test]
+    fn table_of_another_form_fails() {
+        let sql = "CREATE TABLE shop.order_2024 OF shop.order_row;";
+        let schema = Schema::from_sections(&[("order_2024", "TABLE", sql)]);
+        assert!(Table::parse(schema.sections().next().unwrap()).is_err());
+    }
+
+    #[test]
+    fn function_round_trips_dollar_quoted_body() {
+        let function = parse_function(
+            "where(integer)",
+            "CREATE FUNCTION shop.\"where\"(\"table\" integer, OUT total bigint) RETURNS bigint\n    \
+             LANGUAGE plpgsql STABLE SECURITY DEFINER\n    SET search_path TO 'shop', 'pg_temp'\n    \
+             AS $$ BEGIN total := \"table\"; END $$;",
+        );
+        assert_eq!(function.name, "where");
+        assert_eq!(function.arguments[0].name.as_deref(), Some("table"));
+        assert_eq!(function.arguments[1].mode, ArgumentMode::Out);
+        assert_eq!(function.volatility, Volatility::Stable);
+        assert!(function.security_definer);
+        assert_eq!(
+            function.config,
+            [("search_path".to_string(), "'shop', 'pg_temp'".to_string())]
+        );
+    }
+
+    #[test]
+    fn function_round_trips_return_body() {
+        let function = parse_function(
+            "sd(integer)",
+            "CREATE FUNCTION shop.sd(a integer) RETURNS integer\n    LANGUAGE sql SECURITY \
+             DEFINER\n    RETURN (a + 1);",
+        );
+        assert_eq!(function.body, "RETURN (a + 1)");
+        // a semicolon in a string doesn't end the body
+        let function = parse_function(
+            "c(text)",
+            "CREATE FUNCTION shop.c(a text) RETURNS text\n    LANGUAGE sql IMMUTABLE\n    RETURN \
+             CASE WHEN (a = 'x;y'::text) THEN 'semi;colon'::text ELSE a END;",
+        );
+        assert!(function.body.ends_with("ELSE a END"));
+    }
+
+    #[test]
+    fn function_round_trips_begin_atomic_body() {
+        let function = parse_function(
+            "at(integer)",
+            "CREATE FUNCTION shop.at(a integer) RETURNS integer\n    LANGUAGE sql\n    BEGIN \
+             ATOMIC\n SELECT a AS a;\nEND;",
+        );
+        assert_eq!(function.body, "BEGIN ATOMIC\n SELECT a AS a;\nEND");
+    }
+
+    #[test]
+    fn function_with_unknown_attribute_fails() {
+        let schema = Schema::from_sections(&[(
+            "sd(integer)",
+            "FUNCTION",
+            "CREATE FUNCTION shop.sd(a integer) RETURNS integer\n    LANGUAGE sql \
+             WEIRD\n    RETURN (a + 1);",
+        )]);
+        assert!(Function::parse(schema.sections().next().unwrap()).is_err());
+    }
+
+    #[test]
+    fn types_round_trip() {
+        let status = parse_type(
+            "status",
+            "TYPE",
+            "CREATE TYPE shop.status AS ENUM (\n    'new',\n    'it''s paid'\n);",
+        );
+        assert!(matches!(status.kind, TypeKind::Enum(labels) if labels == ["new", "it's paid"]));
+        let price = parse_type(
+            "price",
+            "TYPE",
+            "CREATE TYPE shop.price AS (\n\tamount numeric(19,4),\n\t\"end\" character(3)\n);",
+        );
+        assert!(matches!(price.kind, TypeKind::Composite(a) if a[1].name == "end"));
+        let email = parse_type(
+            "email",
+            "DOMAIN",
+            "CREATE DOMAIN shop.email AS text NOT NULL DEFAULT ''::text\n\tCONSTRAINT \
+             email_check CHECK ((VALUE ~ '@'::text));",
+        );
+        let TypeKind::Domain(domain) = email.kind else {
+            panic!("not a domain");
+        };
+        assert!(domain.not_null);
+        assert_eq!(domain.default.as_deref(), Some("''::text"));
+        assert_eq!(domain.checks[0].name, "email_check");
+    }
+
+    #[test]
+    fn signature_types_leave_out_names_and_out_arguments() {
+        assert_eq!(
+            signature_types("(p_id bigint, OUT x integer, VARIADIC p_tags text[])").as_deref(),
+            Some("(bigint, text[])")
+        );
+        assert_eq!(
+            signature_types("(p_at timestamp with time zone)").as_deref(),
+            Some("(timestamp with time zone)")
+        );
+        assert_eq!(signature_types("()").as_deref(), Some("()"));
+    }
+}
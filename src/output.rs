@@ -0,0 +1,408 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+};
+
+// written into every tree we create, listing the files in it. A directory without one wasn't
+// written by us and is never replaced unless forced
+const MANIFEST: &str = ".willpg-manifest";
+
+// builds the tree next to the target and swaps it in, so a failure halfway leaves the previous
+// tree untouched and a reader never sees a partial one
+pub fn write_tree(
+    path: &Path,
+    files: &BTreeMap<PathBuf, String>,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let replaces = check_target(path, force)?;
+    let staging = sibling(path, "new")?;
+    if let Err(e) = write_files(&staging, files) {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+    if !replaces {
+        fs::rename(&staging, path)?;
+        return Ok(());
+    }
+    // there is no portable way to exchange two directories, so the old tree is moved aside first
+    // and moved back if the new one can't take its place
+    let previous = sibling(path, "old")?;
+    fs::rename(path, &previous)?;
+    if let Err(e) = fs::rename(&staging, path) {
+        fs::rename(&previous, path)?;
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e.into());
+    }
+    fs::remove_dir_all(&previous)?;
+    Ok(())
+}
+
+// what a sync changed, by path relative to the output directory
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub added: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for fp in &self.added {
+            writeln!(f, "added    {}", fp.display())?;
+        }
+        for fp in &self.modified {
+            writeln!(f, "modified {}", fp.display())?;
+        }
+        for fp in &self.removed {
+            writeln!(f, "removed  {}", fp.display())?;
+        }
+        write!(
+            f,
+            "{} added, {} modified, {} removed",
+            self.added.len(),
+            self.modified.len(),
+            self.removed.len()
+        )
+    }
+}
+
+// updates the tree in place, only files whose content changed are written so their mtimes stay
+// put otherwise. Files the previous run wrote that are no longer generated are removed, anything
+// else in the directory is left alone
+pub fn sync_tree(
+    path: &Path,
+    files: &BTreeMap<PathBuf, String>,
+    force: bool,
+) -> Result<SyncReport, Box<dyn Error>> {
+    check_target(path, force)?;
+    let previous = read_manifest(path)?;
+    let mut report = SyncReport::default();
+    for (fp, content) in files {
+        let target = path.join(fp);
+        match fs::read(&target) {
+            Ok(existing) if existing == content.as_bytes() => continue,
+            Ok(_) => report.modified.push(fp.clone()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => report.added.push(fp.clone()),
+            Err(e) => return Err(e.into()),
+        }
+        fs::create_dir_all(target.parent().ok_or("Section path has no parent")?)?;
+        // through a temporary file so an interrupted sync never leaves half a file
+        let staging = target.with_file_name(format!(
+            ".{}.willpg-new-{}",
+            fp.file_name().unwrap_or_default().to_string_lossy(),
+            process::id()
+        ));
+        fs::write(&staging, content)?;
+        fs::rename(&staging, &target)?;
+    }
+    for fp in previous {
+        if files.contains_key(&fp) {
+            continue;
+        }
+        match fs::remove_file(path.join(&fp)) {
+            Ok(()) => report.removed.push(fp.clone()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+        remove_empty_parents(path, &fp);
+    }
+    if !report.added.is_empty() || !report.removed.is_empty() || !path.join(MANIFEST).is_file() {
+        fs::write(path.join(MANIFEST), manifest(files))?;
+    }
+    Ok(report)
+}
+
+// the files the last run wrote, empty when there was none
+pub fn read_manifest(path: &Path) -> Result<BTreeSet<PathBuf>, Box<dyn Error>> {
+    let manifest = match fs::read_to_string(path.join(MANIFEST)) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(manifest
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+// e.g. tables/old_schema once its last table is gone
+fn remove_empty_parents(path: &Path, fp: &Path) {
+    for parent in fp.ancestors().skip(1) {
+        if parent.as_os_str().is_empty() || fs::remove_dir(path.join(parent)).is_err() {
+            break;
+        }
+    }
+}
+
+fn manifest(files: &BTreeMap<PathBuf, String>) -> String {
+    let mut manifest =
+        String::from("# written by willpg, the files it manages in this directory\n");
+    for fp in files.keys() {
+        manifest.push_str(&format!("{}\n", fp.display()));
+    }
+    manifest
+}
+
+// whether there is a tree to replace, refusing anything we can't tell is ours
+fn check_target(path: &Path, force: bool) -> Result<bool, Box<dyn Error>> {
+    let mut entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::NotADirectory => {
+            return Err(format!("{} exists and is not a directory", path.display()).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if force || entries.next().is_none() || path.join(MANIFEST).is_file() {
+        return Ok(true);
+    }
+    Err(format!(
+        "refusing to replace {}, it has no {MANIFEST} so it wasn't written by willpg, use --force \
+         to replace it anyway",
+        path.display()
+    )
+    .into())
+}
+
+// `out` becomes `.out.willpg-new-1234` in the same parent, so the final rename stays on one
+// filesystem
+fn sibling(path: &Path, purpose: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("{} has no directory name", path.display()))?;
+    let sibling = path.with_file_name(format!(
+        ".{}.willpg-{purpose}-{}",
+        name.to_string_lossy(),
+        process::id()
+    ));
+    // left behind by a run that was killed
+    if sibling.exists() {
+        fs::remove_dir_all(&sibling)?;
+    }
+    Ok(sibling)
+}
+
+// a fresh directory under the system's temp dir for a test, left over ones are cleared first
+#[cfg(test)]
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("willpg-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_files(root: &Path, files: &BTreeMap<PathBuf, String>) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(root)?;
+    for (fp, content) in files {
+        let fp = root.join(fp);
+        fs::create_dir_all(fp.parent().ok_or("Section path has no parent")?)?;
+        fs::write(fp, content)?;
+    }
+    fs::write(root.join(MANIFEST), manifest(files))?;
+    Ok(())
+}
+
+// runs the script in one transaction that stops at the first error, so a failed apply leaves the
+// database as it was
+pub fn run_psql(db_url: &str, script: &str, psql_path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut child = Command::new(psql_path)
+        .arg(db_url)
+        .args([
+            "-X",
+            "-q",
+            "-v",
+            "ON_ERROR_STOP=1",
+            "--single-transaction",
+            "-f",
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        // the results of the set_config calls in the prologue
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => format!("{} not found", psql_path.display()),
+            _ => format!("failed to run {}: {e}", psql_path.display()),
+        })?;
+    let mut stdin = child.stdin.take().ok_or("psql has no stdin")?;
+    // psql exits on the first error and closes its end, its own message says what went wrong
+    let written = stdin.write_all(script.as_bytes());
+    drop(stdin);
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("psql failed with {status}, nothing was applied").into());
+    }
+    written?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::read_dump;
+    use crate::structs::{Schema, WriteOptions};
+
+    fn files(files: &[(&str, &str)]) -> BTreeMap<PathBuf, String> {
+        files
+            .iter()
+            .map(|(fp, content)| (PathBuf::from(fp), content.to_string()))
+            .collect()
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn write_tree_only_replaces_its_own_trees() {
+        let dir = scratch_dir("write-tree");
+        let out = dir.join("out");
+        fs::create_dir(&out).unwrap();
+        fs::write(out.join("notes.txt"), "mine").unwrap();
+        let tree = files(&[(
+            "tables/shop/customer.sql",
+            "CREATE TABLE shop.customer ();\n",
+        )]);
+        let e = write_tree(&out, &tree, false).unwrap_err();
+        assert!(e.to_string().starts_with("refusing to replace"));
+        assert_eq!(entries(&out), ["notes.txt"]);
+
+        write_tree(&out, &tree, true).unwrap();
+        assert_eq!(entries(&out), [".willpg-manifest", "tables"]);
+        assert_eq!(
+            fs::read_to_string(out.join(MANIFEST)).unwrap(),
+            "# written by willpg, the files it manages in this directory\n\
+             tables/shop/customer.sql\n"
+        );
+
+        // a tree with a manifest is replaced without --force, and nothing is left beside it
+        let tree = files(&[("general.sql", "CREATE SCHEMA shop;\n")]);
+        write_tree(&out, &tree, false).unwrap();
+        assert_eq!(entries(&out), [".willpg-manifest", "general.sql"]);
+        assert_eq!(entries(&dir), ["out"]);
+
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+        let e = write_tree(&file, &tree, true).unwrap_err();
+        assert!(e.to_string().ends_with("exists and is not a directory"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_tree_creates_missing_and_empty_targets() {
+        let dir = scratch_dir("write-tree-new");
+        let tree = files(&[("general.sql", "CREATE SCHEMA shop;\n")]);
+        write_tree(&dir.join("new"), &tree, false).unwrap();
+        fs::create_dir(dir.join("empty")).unwrap();
+        write_tree(&dir.join("empty"), &tree, false).unwrap();
+        assert_eq!(entries(&dir), ["empty", "new"]);
+        assert_eq!(
+            read_manifest(&dir.join("new")).unwrap(),
+            BTreeSet::from([PathBuf::from("general.sql")])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sync_tree_reports_what_changed() {
+        let dir = scratch_dir("sync-tree");
+        let out = dir.join("out");
+        let first = files(&[
+            ("general.sql", "CREATE SCHEMA shop;\n"),
+            ("tables/old/gone.sql", "CREATE TABLE old.gone ();\n"),
+            (
+                "tables/shop/customer.sql",
+                "CREATE TABLE shop.customer ();\n",
+            ),
+        ]);
+        let report = sync_tree(&out, &first, false).unwrap();
+        assert_eq!(report.added.len(), 3);
+        fs::write(out.join("notes.txt"), "mine").unwrap();
+
+        let second = files(&[
+            ("general.sql", "CREATE SCHEMA shop;\n"),
+            (
+                "tables/shop/customer.sql",
+                "CREATE TABLE shop.customer (id integer);\n",
+            ),
+            ("tables/shop/order.sql", "CREATE TABLE shop.\"order\" ();\n"),
+        ]);
+        let report = sync_tree(&out, &second, false).unwrap();
+        assert_eq!(report.added, [PathBuf::from("tables/shop/order.sql")]);
+        assert_eq!(report.modified, [PathBuf::from("tables/shop/customer.sql")]);
+        assert_eq!(report.removed, [PathBuf::from("tables/old/gone.sql")]);
+        assert_eq!(
+            report.to_string().lines().last(),
+            Some("1 added, 1 modified, 1 removed")
+        );
+        // the emptied schema folder goes, files we didn't write stay
+        assert_eq!(entries(&out.join("tables")), ["shop"]);
+        assert_eq!(
+            entries(&out),
+            [".willpg-manifest", "general.sql", "notes.txt", "tables"]
+        );
+        assert_eq!(
+            read_manifest(&out).unwrap(),
+            second.keys().cloned().collect()
+        );
+
+        let report = sync_tree(&out, &second, false).unwrap();
+        assert!(report.added.is_empty() && report.modified.is_empty() && report.removed.is_empty());
+        assert_eq!(entries(&dir), ["out"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restore_script_from_a_written_tree_creates_dependencies_first() {
+        let dump = "--\n-- PostgreSQL database dump\n--\n\n\
+            SET client_encoding = 'UTF8';\n\n\
+            --\n-- Name: order order_customer_fkey; Type: FK CONSTRAINT; Schema: shop; Owner: postgres\n--\n\n\
+            ALTER TABLE ONLY shop.\"order\"\n    \
+            ADD CONSTRAINT order_customer_fkey FOREIGN KEY (customer_id) REFERENCES shop.customer(id);\n\n\n\
+            --\n-- Name: order order_stamp; Type: TRIGGER; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE TRIGGER order_stamp BEFORE UPDATE ON shop.\"order\" FOR EACH ROW \
+            EXECUTE FUNCTION shop.stamp();\n\n\n\
+            --\n-- Name: big_orders; Type: VIEW; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE VIEW shop.big_orders AS\n SELECT id FROM shop.\"order\";\n\n\n\
+            --\n-- Name: order; Type: TABLE; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE TABLE shop.\"order\" (\n    id integer,\n    customer_id integer\n);\n\n\n\
+            --\n-- Name: stamp(); Type: FUNCTION; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE FUNCTION shop.stamp() RETURNS trigger\n    LANGUAGE plpgsql\n    \
+            AS $$ BEGIN RETURN NEW; END $$;\n\n\n\
+            --\n-- Name: customer; Type: TABLE; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE TABLE shop.customer (\n    id integer\n);\n\n\n\
+            --\n-- Name: shop; Type: SCHEMA; Schema: -; Owner: postgres\n--\n\n\
+            CREATE SCHEMA shop;\n";
+        let dir = scratch_dir("restore-script");
+        let out = dir.join("out");
+        let schema = dump.parse::<Schema>().unwrap();
+        schema.write_to_fs(&out, &WriteOptions::default()).unwrap();
+        let text = read_dump(&out).unwrap().text;
+        let script = text.parse::<Schema>().unwrap().restore_script().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        let position = |statement: &str| {
+            script
+                .find(statement)
+                .unwrap_or_else(|| panic!("{statement} missing from\n{script}"))
+        };
+        assert!(script.starts_with("SET client_encoding = 'UTF8';\n"));
+        assert!(position("CREATE SCHEMA shop;") < position("CREATE FUNCTION shop.stamp()"));
+        assert!(position("CREATE SCHEMA shop;") < position("CREATE TABLE shop.customer"));
+        assert!(position("CREATE TABLE shop.\"order\"") < position("CREATE VIEW"));
+        assert!(position("CREATE TABLE shop.customer") < position("ADD CONSTRAINT"));
+        assert!(position("CREATE TABLE shop.\"order\"") < position("ADD CONSTRAINT"));
+        assert!(position("CREATE FUNCTION shop.stamp()") < position("CREATE TRIGGER"));
+        assert!(position("CREATE TABLE shop.\"order\"") < position("CREATE TRIGGER"));
+    }
+}
@@ -1,25 +1,279 @@
-use std::{error::Error, fs, io::Write, path::Path, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::graph::DependencyGraph;
+use crate::output::{SyncReport, sync_tree, write_tree};
 
 const SECTION_HEADER_BOUNDARY_PATTERN: &str = "\n--\n";
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ObjectType {
     Table,
+    ForeignTable,
+    TableAttach,
+    TableData,
+    View,
+    MaterializedView,
+    MaterializedViewData,
     FkConstraint,
+    Constraint,
+    CheckConstraint,
     Type,
+    ShellType,
+    Domain,
     Trigger,
+    EventTrigger,
     Sequence,
+    SequenceOwnedBy,
+    SequenceSet,
     Function,
+    Procedure,
+    Aggregate,
     Comment,
+    SecurityLabel,
+    Acl,
     DefaultAcl,
     Index,
+    IndexAttach,
+    Statistics,
+    StatisticsData,
     Extension,
     Schema,
-    Domain,
     Default,
-    Constraint,
-    Acl,
-    SequenceOwnedBy,
+    Policy,
+    RowSecurity,
+    Rule,
+    Cast,
+    Collation,
+    Conversion,
+    ProceduralLanguage,
+    AccessMethod,
+    Transform,
+    Operator,
+    OperatorClass,
+    OperatorFamily,
+    TextSearchConfiguration,
+    TextSearchDictionary,
+    TextSearchParser,
+    TextSearchTemplate,
+    ForeignDataWrapper,
+    Server,
+    UserMapping,
+    Publication,
+    PublicationTable,
+    PublicationTablesInSchema,
+    Subscription,
+    SubscriptionTable,
+    LargeObject,
+    LargeObjectData,
+    Database,
+    DatabaseProperties,
+    Encoding,
+    StdStrings,
+    SearchPath,
+}
+
+impl ObjectType {
+    const ALL: [ObjectType; 63] = [
+        ObjectType::Table,
+        ObjectType::ForeignTable,
+        ObjectType::TableAttach,
+        ObjectType::TableData,
+        ObjectType::View,
+        ObjectType::MaterializedView,
+        ObjectType::MaterializedViewData,
+        ObjectType::FkConstraint,
+        ObjectType::Constraint,
+        ObjectType::CheckConstraint,
+        ObjectType::Type,
+        ObjectType::ShellType,
+        ObjectType::Domain,
+        ObjectType::Trigger,
+        ObjectType::EventTrigger,
+        ObjectType::Sequence,
+        ObjectType::SequenceOwnedBy,
+        ObjectType::SequenceSet,
+        ObjectType::Function,
+        ObjectType::Procedure,
+        ObjectType::Aggregate,
+        ObjectType::Comment,
+        ObjectType::SecurityLabel,
+        ObjectType::Acl,
+        ObjectType::DefaultAcl,
+        ObjectType::Index,
+        ObjectType::IndexAttach,
+        ObjectType::Statistics,
+        ObjectType::StatisticsData,
+        ObjectType::Extension,
+        ObjectType::Schema,
+        ObjectType::Default,
+        ObjectType::Policy,
+        ObjectType::RowSecurity,
+        ObjectType::Rule,
+        ObjectType::Cast,
+        ObjectType::Collation,
+        ObjectType::Conversion,
+        ObjectType::ProceduralLanguage,
+        ObjectType::AccessMethod,
+        ObjectType::Transform,
+        ObjectType::Operator,
+        ObjectType::OperatorClass,
+        ObjectType::OperatorFamily,
+        ObjectType::TextSearchConfiguration,
+        ObjectType::TextSearchDictionary,
+        ObjectType::TextSearchParser,
+        ObjectType::TextSearchTemplate,
+        ObjectType::ForeignDataWrapper,
+        ObjectType::Server,
+        ObjectType::UserMapping,
+        ObjectType::Publication,
+        ObjectType::PublicationTable,
+        ObjectType::PublicationTablesInSchema,
+        ObjectType::Subscription,
+        ObjectType::SubscriptionTable,
+        ObjectType::LargeObject,
+        ObjectType::LargeObjectData,
+        ObjectType::Database,
+        ObjectType::DatabaseProperties,
+        ObjectType::Encoding,
+        ObjectType::StdStrings,
+        ObjectType::SearchPath,
+    ];
+
+    // the order sections sharing a file are written in, close to the order pg_dump restores
+    // them so a file replays on its own, e.g. a table before its constraints and a primary key
+    // before a foreign key that may reference it
+    pub fn restore_rank(self) -> u8 {
+        match self {
+            ObjectType::Encoding
+            | ObjectType::StdStrings
+            | ObjectType::SearchPath
+            | ObjectType::Database
+            | ObjectType::DatabaseProperties => 0,
+            ObjectType::Schema => 1,
+            ObjectType::Extension
+            | ObjectType::ProceduralLanguage
+            | ObjectType::AccessMethod
+            | ObjectType::ForeignDataWrapper => 2,
+            ObjectType::ShellType => 3,
+            ObjectType::Type
+            | ObjectType::Domain
+            | ObjectType::Collation
+            | ObjectType::Conversion
+            | ObjectType::TextSearchParser
+            | ObjectType::TextSearchTemplate
+            | ObjectType::TextSearchDictionary
+            | ObjectType::TextSearchConfiguration => 4,
+            ObjectType::Function | ObjectType::Procedure | ObjectType::Aggregate => 5,
+            ObjectType::Operator
+            | ObjectType::OperatorFamily
+            | ObjectType::OperatorClass
+            | ObjectType::Cast
+            | ObjectType::Transform => 6,
+            ObjectType::Server | ObjectType::UserMapping => 7,
+            ObjectType::Table | ObjectType::ForeignTable => 8,
+            ObjectType::View | ObjectType::MaterializedView => 9,
+            ObjectType::TableAttach => 10,
+            ObjectType::Sequence => 11,
+            ObjectType::SequenceOwnedBy => 12,
+            ObjectType::Default => 13,
+            ObjectType::Constraint | ObjectType::CheckConstraint => 14,
+            ObjectType::Index | ObjectType::Statistics => 15,
+            ObjectType::IndexAttach => 16,
+            ObjectType::FkConstraint => 17,
+            ObjectType::Rule => 18,
+            ObjectType::Trigger | ObjectType::EventTrigger => 19,
+            ObjectType::RowSecurity => 20,
+            ObjectType::Policy => 21,
+            ObjectType::Publication | ObjectType::Subscription => 22,
+            ObjectType::PublicationTable
+            | ObjectType::PublicationTablesInSchema
+            | ObjectType::SubscriptionTable => 23,
+            ObjectType::TableData
+            | ObjectType::SequenceSet
+            | ObjectType::LargeObject
+            | ObjectType::LargeObjectData
+            | ObjectType::MaterializedViewData
+            | ObjectType::StatisticsData => 24,
+            ObjectType::Comment | ObjectType::SecurityLabel => 25,
+            ObjectType::Acl => 26,
+            ObjectType::DefaultAcl => 27,
+        }
+    }
+
+    // the type string pg_dump writes in the section header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectType::Table => "TABLE",
+            ObjectType::ForeignTable => "FOREIGN TABLE",
+            ObjectType::TableAttach => "TABLE ATTACH",
+            ObjectType::TableData => "TABLE DATA",
+            ObjectType::View => "VIEW",
+            ObjectType::MaterializedView => "MATERIALIZED VIEW",
+            ObjectType::MaterializedViewData => "MATERIALIZED VIEW DATA",
+            ObjectType::FkConstraint => "FK CONSTRAINT",
+            ObjectType::Constraint => "CONSTRAINT",
+            ObjectType::CheckConstraint => "CHECK CONSTRAINT",
+            ObjectType::Type => "TYPE",
+            ObjectType::ShellType => "SHELL TYPE",
+            ObjectType::Domain => "DOMAIN",
+            ObjectType::Trigger => "TRIGGER",
+            ObjectType::EventTrigger => "EVENT TRIGGER",
+            ObjectType::Sequence => "SEQUENCE",
+            ObjectType::SequenceOwnedBy => "SEQUENCE OWNED BY",
+            ObjectType::SequenceSet => "SEQUENCE SET",
+            ObjectType::Function => "FUNCTION",
+            ObjectType::Procedure => "PROCEDURE",
+            ObjectType::Aggregate => "AGGREGATE",
+            ObjectType::Comment => "COMMENT",
+            ObjectType::SecurityLabel => "SECURITY LABEL",
+            ObjectType::Acl => "ACL",
+            ObjectType::DefaultAcl => "DEFAULT ACL",
+            ObjectType::Index => "INDEX",
+            ObjectType::IndexAttach => "INDEX ATTACH",
+            ObjectType::Statistics => "STATISTICS",
+            ObjectType::StatisticsData => "STATISTICS DATA",
+            ObjectType::Extension => "EXTENSION",
+            ObjectType::Schema => "SCHEMA",
+            ObjectType::Default => "DEFAULT",
+            ObjectType::Policy => "POLICY",
+            ObjectType::RowSecurity => "ROW SECURITY",
+            ObjectType::Rule => "RULE",
+            ObjectType::Cast => "CAST",
+            ObjectType::Collation => "COLLATION",
+            ObjectType::Conversion => "CONVERSION",
+            ObjectType::ProceduralLanguage => "PROCEDURAL LANGUAGE",
+            ObjectType::AccessMethod => "ACCESS METHOD",
+            ObjectType::Transform => "TRANSFORM",
+            ObjectType::Operator => "OPERATOR",
+            ObjectType::OperatorClass => "OPERATOR CLASS",
+            ObjectType::OperatorFamily => "OPERATOR FAMILY",
+            ObjectType::TextSearchConfiguration => "TEXT SEARCH CONFIGURATION",
+            ObjectType::TextSearchDictionary => "TEXT SEARCH DICTIONARY",
+            ObjectType::TextSearchParser => "TEXT SEARCH PARSER",
+            ObjectType::TextSearchTemplate => "TEXT SEARCH TEMPLATE",
+            ObjectType::ForeignDataWrapper => "FOREIGN DATA WRAPPER",
+            ObjectType::Server => "SERVER",
+            ObjectType::UserMapping => "USER MAPPING",
+            ObjectType::Publication => "PUBLICATION",
+            ObjectType::PublicationTable => "PUBLICATION TABLE",
+            ObjectType::PublicationTablesInSchema => "PUBLICATION TABLES IN SCHEMA",
+            ObjectType::Subscription => "SUBSCRIPTION",
+            ObjectType::SubscriptionTable => "SUBSCRIPTION TABLE",
+            ObjectType::LargeObject => "LARGE OBJECT",
+            ObjectType::LargeObjectData => "LARGE OBJECTS",
+            ObjectType::Database => "DATABASE",
+            ObjectType::DatabaseProperties => "DATABASE PROPERTIES",
+            ObjectType::Encoding => "ENCODING",
+            ObjectType::StdStrings => "STDSTRINGS",
+            ObjectType::SearchPath => "SEARCHPATH",
+        }
+    }
 }
 
 impl FromStr for ObjectType {
@@ -27,39 +281,70 @@ impl FromStr for ObjectType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "TABLE" => Ok(ObjectType::Table),
-            "FK CONSTRAINT" => Ok(ObjectType::FkConstraint),
-            "TYPE" => Ok(ObjectType::Type),
-            "TRIGGER" => Ok(ObjectType::Trigger),
-            "SEQUENCE" => Ok(ObjectType::Sequence),
-            "FUNCTION" => Ok(ObjectType::Function),
-            "COMMENT" => Ok(ObjectType::Comment),
-            "DEFAULT ACL" => Ok(ObjectType::DefaultAcl),
-            "INDEX" => Ok(ObjectType::Index),
-            "EXTENSION" => Ok(ObjectType::Extension),
-            "SCHEMA" => Ok(ObjectType::Schema),
-            "DOMAIN" => Ok(ObjectType::Domain),
-            "DEFAULT" => Ok(ObjectType::Default),
-            "CONSTRAINT" => Ok(ObjectType::Constraint),
-            "ACL" => Ok(ObjectType::Acl),
-            "SEQUENCE OWNED BY" => Ok(ObjectType::SequenceOwnedBy),
-            _ => Err(format!("Unknown object type: {s}").into()),
+            // older pg_dump versions call large objects blobs
+            "BLOB" | "BLOB METADATA" => Ok(ObjectType::LargeObject),
+            "BLOBS" => Ok(ObjectType::LargeObjectData),
+            _ => ObjectType::ALL
+                .into_iter()
+                .find(|object_type| object_type.as_str() == s)
+                .ok_or_else(|| format!("Unknown object type: {s}").into()),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct SchemaHeader {
-    name: String,
+    pub name: String,
+    pub signature: Option<String>,
+    pub object_type: ObjectType,
+    pub schema: String,
+    pub owner: String,
+    // only known for verbose dumps and archives
+    pub dump_id: Option<u32>,
+    pub dependencies: Vec<u32>,
+}
+
+// an entry of `pg_restore --list`, e.g.
+// 230; 1255 16500 FUNCTION shop admin_reset(bigint) postgres
+#[derive(Debug)]
+pub struct TocEntry {
+    dump_id: u32,
     object_type: ObjectType,
     schema: String,
-    _owner: String,
+    owner: String,
+}
+
+impl FromStr for TocEntry {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (dump_id, rest) = s.split_once("; ").ok_or("Missing dump id")?;
+        // skip the catalog table and object oids
+        let rest = rest.splitn(3, ' ').nth(2).ok_or("Missing object type")?;
+        // the type is the longest known type the entry starts with, e.g. `FK CONSTRAINT` over
+        // `FK`, `SEQUENCE OWNED BY` over `SEQUENCE`
+        let object_type = ObjectType::ALL
+            .into_iter()
+            .filter(|object_type| rest.starts_with(&format!("{} ", object_type.as_str())))
+            .max_by_key(|object_type| object_type.as_str().len())
+            .ok_or_else(|| format!("Unknown object type in TOC entry: {s}"))?;
+        let rest = &rest[object_type.as_str().len() + 1..];
+        let (schema, rest) = rest.split_once(' ').ok_or("Missing schema")?;
+        // the owner is the last field and can be empty, e.g. for casts
+        let (_, owner) = rest.rsplit_once(' ').ok_or("Missing owner")?;
+        Ok(TocEntry {
+            dump_id: dump_id.trim().parse()?,
+            object_type,
+            schema: schema.to_string(),
+            owner: owner.to_string(),
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct SchemaSection {
-    header: SchemaHeader,
-    body: String,
+    pub header: SchemaHeader,
+    pub body: String,
 }
 
 // instead of storing schema we could have a bin for each of the known body_types, these will need
@@ -67,45 +352,427 @@ pub struct SchemaSection {
 #[derive(Debug, Default)]
 pub struct Schema {
     tables: Vec<SchemaSection>,
+    views: Vec<SchemaSection>,
     types: Vec<SchemaSection>,
     functions: Vec<SchemaSection>,
+    triggers: Vec<SchemaSection>,
     constraints: Vec<SchemaSection>,
     indexes: Vec<SchemaSection>,
+    sequences: Vec<SchemaSection>,
+    defaults: Vec<SchemaSection>,
+    policies: Vec<SchemaSection>,
+    rules: Vec<SchemaSection>,
+    // objects that get a top level folder of their own, e.g. collations or publications
+    standalone: Vec<SchemaSection>,
+    data: Vec<SchemaSection>,
+    acls: Vec<SchemaSection>,
     comments: Vec<SchemaSection>,
     general: Vec<SchemaSection>,
+    metadata: DumpMetadata,
+    diagnostics: Vec<Diagnostic>,
+}
+
+// what the dump says about itself and the session it restores in, everything above the first
+// section header
+#[derive(Debug, Default)]
+pub struct DumpMetadata {
+    pub server_version: Option<PgVersion>,
+    pub client_version: Option<PgVersion>,
+    // `SET name = value;` in the order they appear
+    pub settings: Vec<(String, String)>,
+    // the value passed to set_config('search_path', ...), an empty string pins it to nothing
+    pub search_path: Option<String>,
+}
+
+// 17.6 (Debian 17.6-2.pgdg12+1), releases before 10 have a third number, e.g. 9.6.24
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: Option<u32>,
+    // the packaging details in brackets after the number
+    pub build: Option<String>,
+}
+
+// how the schema is laid out on disk
+#[derive(clap::Args, Debug, Default)]
+pub struct WriteOptions {
+    /// Note the function each trigger executes next to the trigger, and the trigger in the
+    /// function's file
+    #[arg(long)]
+    pub link_trigger_functions: bool,
+
+    /// Leave what changes on every dump out of 00_prologue.sql, the version banners
+    #[arg(long)]
+    pub strip_volatile: bool,
+
+    /// Replace the output directory even when it wasn't written by willpg
+    #[arg(long)]
+    pub force: bool,
+
+    /// Update the output directory in place, writing only the files that changed and removing
+    /// the ones that are no longer generated
+    #[arg(long)]
+    pub sync: bool,
+}
+
+// a section that was in the dump but couldn't be classified, so it's missing from the output
+#[derive(Debug)]
+pub struct Diagnostic {
+    // None when the problem came from the archive TOC rather than the text
+    pub line: Option<usize>,
+    pub header: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: skipped `{}`: {}", self.header, self.reason),
+            None => write!(f, "skipped `{}`: {}", self.header, self.reason),
+        }
+    }
+}
+
+impl FromStr for PgVersion {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, build) = match s.trim().split_once(' ') {
+            Some((number, build)) => (number, Some(build.to_string())),
+            None => (s.trim(), None),
+        };
+        let mut parts = number.split('.');
+        let major = parts
+            .next()
+            .ok_or("Missing major version")?
+            .parse::<u32>()?;
+        let minor = parts
+            .next()
+            .ok_or("Missing minor version")?
+            .parse::<u32>()?;
+        let patch = parts.next().map(|patch| patch.parse::<u32>()).transpose()?;
+        Ok(PgVersion {
+            major,
+            minor,
+            patch,
+            build,
+        })
+    }
+}
+
+impl fmt::Display for PgVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if let Some(patch) = self.patch {
+            write!(f, ".{patch}")?;
+        }
+        if let Some(build) = &self.build {
+            write!(f, " {build}")?;
+        }
+        Ok(())
+    }
+}
+
+impl DumpMetadata {
+    // takes a line of the dump preamble, returns false when it isn't one we know
+    fn read_line(&mut self, line: &str) -> bool {
+        // recent pg_dumps open with `\restrict <key>` and close with the matching `\unrestrict`,
+        // the pair only guards replaying that one file, so it's left out of the trees we write
+        if line.starts_with("\\restrict ") {
+            return true;
+        }
+        if let Some(version) = line.strip_prefix("-- Dumped from database version ") {
+            self.server_version = version.parse().ok();
+        } else if let Some(version) = line.strip_prefix("-- Dumped by pg_dump version ") {
+            self.client_version = version.parse().ok();
+        } else if let Some(value) = line
+            .strip_prefix("SELECT pg_catalog.set_config('search_path', ")
+            .and_then(|rest| rest.strip_suffix(", false);"))
+        {
+            self.search_path = Some(value.trim_matches('\'').replace("''", "'"));
+        } else {
+            return self.read_setting(line);
+        }
+        true
+    }
+
+    // SET default_tablespace = '';
+    // a setting that is already known with another value is per object, so it isn't taken
+    fn read_setting(&mut self, line: &str) -> bool {
+        let Some((name, value)) = line
+            .strip_prefix("SET ")
+            .and_then(|rest| rest.strip_suffix(';'))
+            .and_then(|rest| rest.split_once(" = "))
+        else {
+            return false;
+        };
+        match self.settings.iter().find(|(known, _)| known == name) {
+            Some((_, known)) => known == value,
+            None => {
+                self.settings.push((name.to_string(), value.to_string()));
+                true
+            }
+        }
+    }
+
+    // pg_dump sets up the session for the next object at the end of the section before it, e.g.
+    // `SET default_table_access_method = heap;` ahead of the first table
+    fn take_trailing_settings(&mut self, body: &str) -> String {
+        let mut rest = body.trim_end_matches('\n');
+        let mut taken = Vec::new();
+        while let Some((before, line)) = rest.rsplit_once('\n')
+            && line.starts_with("SET ")
+        {
+            taken.push(line);
+            rest = before.trim_end_matches('\n');
+        }
+        if taken.is_empty() {
+            return body.to_string();
+        }
+        // recorded in the order they were written
+        let mut kept = Vec::new();
+        for line in taken.into_iter().rev() {
+            if !self.read_setting(line) {
+                kept.push(line);
+            }
+        }
+        let mut body = format!("{rest}\n");
+        for line in kept {
+            body.push_str(&format!("\n{line}\n"));
+        }
+        body
+    }
+
+    fn is_empty(&self) -> bool {
+        self.server_version.is_none()
+            && self.client_version.is_none()
+            && self.settings.is_empty()
+            && self.search_path.is_none()
+    }
+
+    // replaying this first puts a session in the state the rest of the tree expects
+    fn prologue(&self, strip_volatile: bool) -> String {
+        let mut prologue = String::new();
+        if !strip_volatile {
+            if let Some(version) = &self.server_version {
+                prologue.push_str(&format!("-- Dumped from database version {version}\n"));
+            }
+            if let Some(version) = &self.client_version {
+                prologue.push_str(&format!("-- Dumped by pg_dump version {version}\n"));
+            }
+            prologue.push('\n');
+        }
+        for (name, value) in &self.settings {
+            prologue.push_str(&format!("SET {name} = {value};\n"));
+        }
+        if let Some(search_path) = &self.search_path {
+            prologue.push_str(&format!(
+                "SELECT pg_catalog.set_config('search_path', '{}', false);\n",
+                search_path.replace('\'', "''")
+            ));
+        }
+        prologue
+    }
 }
 
 impl FromStr for SchemaHeader {
     type Err = Box<dyn Error>;
 
+    // verbose dumps, including the text pg_restore produces from an archive, put the TOC entry
+    // and its dependencies above the name line:
+    // -- TOC entry 3414 (class 0 OID 0)
+    // -- Dependencies: 6
+    // -- Name: SCHEMA shop; Type: COMMENT; Schema: -; Owner: postgres
+    // -- Data Pos: 0
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let content = s.strip_prefix("-- ").ok_or("Missing prefix")?;
+        let mut dump_id = None;
+        let mut dependencies = Vec::new();
+        let mut name_line = None;
+        for line in s.lines() {
+            let line = line.strip_prefix("-- ").ok_or("Missing prefix")?;
+            if let Some(entry) = line.strip_prefix("TOC entry ") {
+                let id = entry
+                    .split_whitespace()
+                    .next()
+                    .ok_or("Missing TOC entry id")?;
+                dump_id = Some(id.parse::<u32>()?);
+            } else if let Some(ids) = line.strip_prefix("Dependencies: ") {
+                for id in ids.split_whitespace() {
+                    dependencies.push(id.parse::<u32>()?);
+                }
+            } else if line.starts_with("Name: ") || line.starts_with("Data for Name: ") {
+                name_line = Some(line);
+            }
+        }
+        let content = name_line.ok_or("Missing Name")?;
+        // data sections are headed `-- Data for Name: ...`
+        let content = content.strip_prefix("Data for ").unwrap_or(content);
         let parts: Vec<&str> = content.split("; ").collect();
+        // functions are named with their arguments, e.g. `add_image(p_url text)`, which we keep
+        // apart so overloads can be told apart. Casts are named `CAST (integer AS text)` and keep
+        // their brackets
+        let full_name = parts[0].strip_prefix("Name: ").ok_or("Missing Name")?;
+        let (name, signature) = match full_name.split_once('(') {
+            Some((name, args)) if !name.ends_with(' ') => (name, Some(args)),
+            _ => (full_name, None),
+        };
 
         Ok(SchemaHeader {
-            name: parts[0]
-                .strip_prefix("Name: ")
-                .ok_or("Missing Name")?
-                .split("(")
-                .next()
-                .ok_or("Couldn't remove parentheses")?
-                .to_string(),
-            object_type: parts[1]
+            name: name.trim_end().to_string(),
+            signature: signature.map(|args| format!("({args}")),
+            object_type: parts
+                .get(1)
+                .ok_or("Missing Type")?
                 .strip_prefix("Type: ")
                 .ok_or("Missing Type")?
                 .parse::<ObjectType>()?,
-            schema: parts[2]
+            schema: parts
+                .get(2)
+                .ok_or("Missing Schema")?
                 .strip_prefix("Schema: ")
                 .ok_or("Missing Schema")?
                 .to_string(),
-            _owner: parts[3]
+            owner: parts
+                .get(3)
+                .ok_or("Missing Owner")?
                 .strip_prefix("Owner: ")
                 .ok_or("Missing Owner")?
                 .to_string(),
+            dump_id,
+            dependencies,
         })
     }
 }
 
+#[cfg(feature = "catalog")]
+impl SchemaHeader {
+    pub fn new(
+        object_type: ObjectType,
+        schema: &str,
+        name: &str,
+        signature: Option<String>,
+        owner: &str,
+    ) -> Self {
+        SchemaHeader {
+            name: name.to_string(),
+            signature,
+            object_type,
+            schema: schema.to_string(),
+            owner: owner.to_string(),
+            dump_id: None,
+            dependencies: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "catalog")]
+impl SchemaSection {
+    pub fn new(header: SchemaHeader, body: String) -> Self {
+        SchemaSection { header, body }
+    }
+}
+
+// object kinds in ACL and COMMENT names that are more than one word long
+const MULTI_WORD_TARGET_KINDS: [&str; 13] = [
+    "MATERIALIZED VIEW",
+    "FOREIGN TABLE",
+    "FOREIGN DATA WRAPPER",
+    "FOREIGN SERVER",
+    "EVENT TRIGGER",
+    "TEXT SEARCH CONFIGURATION",
+    "TEXT SEARCH DICTIONARY",
+    "TEXT SEARCH PARSER",
+    "TEXT SEARCH TEMPLATE",
+    "ACCESS METHOD",
+    "OPERATOR CLASS",
+    "OPERATOR FAMILY",
+    "LARGE OBJECT",
+];
+
+impl SchemaHeader {
+    // ACL and COMMENT headers name the object they refer to, e.g. `FUNCTION add_image` or
+    // `COLUMN "order".paid_at`, this splits that into the object kind and its name
+    pub fn target(&self) -> Option<(&str, &str)> {
+        for kind in MULTI_WORD_TARGET_KINDS {
+            if let Some(name) = self
+                .name
+                .strip_prefix(kind)
+                .and_then(|n| n.strip_prefix(' '))
+            {
+                return Some((kind, name));
+            }
+        }
+        self.name.split_once(' ')
+    }
+}
+
+// the header pg_dump writes above every section, so written files parse like a dump
+// --
+// -- Name: total(a integer); Type: FUNCTION; Schema: shop; Owner: postgres
+// --
+impl fmt::Display for SchemaHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = match self.object_type {
+            ObjectType::TableData | ObjectType::LargeObjectData => "Data for ",
+            _ => "",
+        };
+        write!(
+            f,
+            "--\n-- {data}Name: {}{}; Type: {}; Schema: {}; Owner: {}\n--",
+            self.name,
+            self.signature.as_deref().unwrap_or(""),
+            self.object_type.as_str(),
+            self.schema,
+            self.owner
+        )
+    }
+}
+
+impl SchemaSection {
+    // the header and the statements, without the blank lines pg_dump leaves around them
+    fn render(&self) -> String {
+        format!("{}\n\n{}", self.header, self.body.trim_matches('\n'))
+    }
+
+    // the table a dependent section (constraint, index, policy, ...) is attached to
+    fn table_name(&self) -> Result<String, Box<dyn Error>> {
+        match self.header.object_type {
+            ObjectType::Index => index_table(&self.body),
+            // CREATE STATISTICS dirac.brand_stats ON name, slug FROM dirac.brand;
+            ObjectType::Statistics => Ok(unquote(
+                self.body
+                    .trim_start_matches('\n')
+                    .lines()
+                    .next()
+                    .ok_or("no create statement")?
+                    .rsplit_once(" FROM ")
+                    .ok_or("no from clause")?
+                    .1
+                    .trim_end_matches(|c: char| c == ';' || c.is_whitespace())
+                    .split('.')
+                    .next_back()
+                    .ok_or("no table name")?,
+            )),
+            // these are named `<table>` or `<table> <object>`
+            ObjectType::TableAttach
+            | ObjectType::StatisticsData
+            | ObjectType::Trigger
+            | ObjectType::Default
+            | ObjectType::Policy
+            | ObjectType::RowSecurity
+            | ObjectType::Rule => Ok(unquote(
+                self.header
+                    .name
+                    .split_whitespace()
+                    .next()
+                    .ok_or("no table name")?,
+            )),
+            _ => constraint_table(&self.body),
+        }
+    }
+}
+
 impl FromStr for Schema {
     type Err = Box<dyn Error>;
     // we want to split on --\n
@@ -131,50 +798,48 @@ impl FromStr for Schema {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut schema = Schema::default();
         let mut sh_holder: Option<SchemaHeader> = None;
+        // set when a header couldn't be parsed, so its body isn't mistaken for the next header
+        let mut skip_body = false;
+        let mut line = 1;
         for sec in s.split(SECTION_HEADER_BOUNDARY_PATTERN) {
-            if sh_holder.is_none() {
-                // we are waiting on a valid header
-                sh_holder = sec.parse::<SchemaHeader>().ok()
-            } else if let Some(sh) = sh_holder {
-                // we are waiting on a  body
-                match sh.object_type {
-                    ObjectType::Table => schema.tables.push(SchemaSection {
-                        header: sh,
-                        body: String::from(sec),
-                    }),
-                    ObjectType::Type | ObjectType::Domain => schema.types.push(SchemaSection {
-                        header: sh,
-                        body: String::from(sec),
-                    }),
-                    ObjectType::FkConstraint | ObjectType::Constraint => {
-                        schema.constraints.push(SchemaSection {
-                            header: sh,
-                            body: String::from(sec),
-                        })
+            let sec_line = line;
+            // the boundary pattern holds two newlines
+            line += sec.matches('\n').count() + 2;
+            if skip_body {
+                skip_body = false;
+            } else if sh_holder.is_none() {
+                // we are waiting on a valid header, anything that isn't shaped like one is the
+                // preamble or a separator
+                if !is_header_candidate(sec) {
+                    // only the preamble comes before the first header
+                    if schema.sections_mut().next().is_none() {
+                        for line in sec.lines() {
+                            schema.metadata.read_line(line);
+                        }
                     }
-                    ObjectType::Index => schema.indexes.push(SchemaSection {
-                        header: sh,
-                        body: String::from(sec),
-                    }),
-                    ObjectType::Extension
-                    | ObjectType::Default
-                    | ObjectType::DefaultAcl
-                    | ObjectType::Schema => schema.general.push(SchemaSection {
-                        header: sh,
-                        body: String::from(sec),
-                    }),
-                    ObjectType::Comment => schema.comments.push(SchemaSection {
-                        header: sh,
-                        body: String::from(sec),
-                    }),
-                    ObjectType::Function | ObjectType::Trigger => {
-                        schema.functions.push(SchemaSection {
-                            header: sh,
-                            body: String::from(sec),
-                        })
+                    continue;
+                }
+                match sec.parse::<SchemaHeader>() {
+                    Ok(sh) => sh_holder = Some(sh),
+                    Err(e) => {
+                        schema.diagnostics.push(Diagnostic {
+                            line: Some(sec_line),
+                            header: sec.trim().to_string(),
+                            reason: e.to_string(),
+                        });
+                        skip_body = true;
                     }
-                    _ => (),
                 }
+            } else if let Some(sh) = sh_holder {
+                // we are waiting on a  body
+                // verbose dumps close with a timestamp that would otherwise end up in the body of
+                // the last section
+                let body = match sec.split_once("\n-- Completed on ") {
+                    Some((body, _)) => body,
+                    None => sec,
+                };
+                let body = schema.metadata.take_trailing_settings(body);
+                schema.push(SchemaSection { header: sh, body });
                 sh_holder = None;
             }
         }
@@ -182,94 +847,1218 @@ impl FromStr for Schema {
     }
 }
 
+// -- Name: brand; Type: TABLE; Schema: dirac; Owner: postgres
+// -- Data for Name: brand; Type: TABLE DATA; Schema: dirac; Owner: postgres
+// optionally with the TOC lines of a verbose dump around the name line
+fn is_header_candidate(sec: &str) -> bool {
+    let sec = sec.trim_end();
+    sec.lines().all(|line| line.starts_with("-- "))
+        && sec
+            .lines()
+            .any(|line| line.starts_with("-- Name: ") || line.starts_with("-- Data for Name: "))
+}
+
 impl Schema {
-    pub fn write_to_fs(&self, path: &Path) -> Result<(), Box<dyn Error>> {
-        // delete the existing fs
-        if let Err(e) = fs::remove_dir_all(path) {
-            if e.kind() != std::io::ErrorKind::NotFound {
-                return Err(e.into());
+    // sections are kept in a bin per kind of object, in the order they were added
+    pub fn push(&mut self, section: SchemaSection) {
+        let bin = match section.header.object_type {
+            ObjectType::Table | ObjectType::ForeignTable => &mut self.tables,
+            ObjectType::View | ObjectType::MaterializedView | ObjectType::MaterializedViewData => {
+                &mut self.views
+            }
+            ObjectType::Type | ObjectType::ShellType | ObjectType::Domain => &mut self.types,
+            ObjectType::FkConstraint
+            | ObjectType::Constraint
+            | ObjectType::CheckConstraint
+            | ObjectType::TableAttach => &mut self.constraints,
+            ObjectType::Index
+            | ObjectType::IndexAttach
+            | ObjectType::Statistics
+            | ObjectType::StatisticsData => &mut self.indexes,
+            ObjectType::Function | ObjectType::Procedure | ObjectType::Aggregate => {
+                &mut self.functions
+            }
+            ObjectType::Trigger => &mut self.triggers,
+            ObjectType::Sequence | ObjectType::SequenceOwnedBy | ObjectType::SequenceSet => {
+                &mut self.sequences
+            }
+            ObjectType::Default => &mut self.defaults,
+            ObjectType::Policy | ObjectType::RowSecurity => &mut self.policies,
+            ObjectType::Rule => &mut self.rules,
+            ObjectType::Collation
+            | ObjectType::TextSearchConfiguration
+            | ObjectType::TextSearchDictionary
+            | ObjectType::TextSearchParser
+            | ObjectType::TextSearchTemplate
+            | ObjectType::EventTrigger
+            | ObjectType::Server
+            | ObjectType::UserMapping
+            | ObjectType::Publication
+            | ObjectType::PublicationTable
+            | ObjectType::PublicationTablesInSchema
+            | ObjectType::Subscription
+            | ObjectType::SubscriptionTable => &mut self.standalone,
+            ObjectType::TableData | ObjectType::LargeObject | ObjectType::LargeObjectData => {
+                &mut self.data
+            }
+            ObjectType::Comment | ObjectType::SecurityLabel => &mut self.comments,
+            ObjectType::Acl => &mut self.acls,
+            ObjectType::Extension
+            | ObjectType::Schema
+            | ObjectType::DefaultAcl
+            | ObjectType::Cast
+            | ObjectType::Conversion
+            | ObjectType::ProceduralLanguage
+            | ObjectType::AccessMethod
+            | ObjectType::Transform
+            | ObjectType::Operator
+            | ObjectType::OperatorClass
+            | ObjectType::OperatorFamily
+            | ObjectType::ForeignDataWrapper
+            | ObjectType::Database
+            | ObjectType::DatabaseProperties
+            | ObjectType::Encoding
+            | ObjectType::StdStrings
+            | ObjectType::SearchPath => &mut self.general,
+        };
+        bin.push(section);
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    // a dump with these sections of the shop schema, each a header name, type and statements.
+    // Schemas themselves are in no schema, as pg_dump writes them
+    #[cfg(test)]
+    pub fn from_sections(sections: &[(&str, &str, &str)]) -> Schema {
+        let mut dump = "--\n-- PostgreSQL database dump\n--\n".to_string();
+        for (name, object_type, sql) in sections {
+            let schema = match *object_type {
+                "SCHEMA" => "-",
+                _ => "shop",
+            };
+            dump.push_str(&format!(
+                "\n--\n-- Name: {name}; Type: {object_type}; Schema: {schema}; Owner: postgres\n\
+                 --\n\n{sql}\n\n"
+            ));
+        }
+        dump.parse().unwrap()
+    }
+
+    #[cfg(feature = "catalog")]
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    #[cfg(feature = "catalog")]
+    pub fn set_metadata(&mut self, metadata: DumpMetadata) {
+        self.metadata = metadata;
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = &SchemaSection> {
+        self.tables
+            .iter()
+            .chain(self.views.iter())
+            .chain(self.types.iter())
+            .chain(self.functions.iter())
+            .chain(self.triggers.iter())
+            .chain(self.constraints.iter())
+            .chain(self.indexes.iter())
+            .chain(self.sequences.iter())
+            .chain(self.defaults.iter())
+            .chain(self.policies.iter())
+            .chain(self.rules.iter())
+            .chain(self.standalone.iter())
+            .chain(self.data.iter())
+            .chain(self.acls.iter())
+            .chain(self.comments.iter())
+            .chain(self.general.iter())
+    }
+
+    fn sections_mut(&mut self) -> impl Iterator<Item = &mut SchemaSection> {
+        self.tables
+            .iter_mut()
+            .chain(self.views.iter_mut())
+            .chain(self.types.iter_mut())
+            .chain(self.functions.iter_mut())
+            .chain(self.triggers.iter_mut())
+            .chain(self.constraints.iter_mut())
+            .chain(self.indexes.iter_mut())
+            .chain(self.sequences.iter_mut())
+            .chain(self.defaults.iter_mut())
+            .chain(self.policies.iter_mut())
+            .chain(self.rules.iter_mut())
+            .chain(self.standalone.iter_mut())
+            .chain(self.data.iter_mut())
+            .chain(self.acls.iter_mut())
+            .chain(self.comments.iter_mut())
+            .chain(self.general.iter_mut())
+    }
+
+    // the archive TOC is authoritative for the owner and schema of an entry, the text headers
+    // leave them blank for some object types. Entries whose type disagrees with the text are
+    // reported rather than trusted
+    pub fn apply_toc(&mut self, toc: &[TocEntry]) {
+        let entries: HashMap<u32, &TocEntry> =
+            toc.iter().map(|entry| (entry.dump_id, entry)).collect();
+        let mut mismatches = Vec::new();
+        for section in self.sections_mut() {
+            let header = &mut section.header;
+            let Some(entry) = header.dump_id.and_then(|id| entries.get(&id)) else {
+                continue;
+            };
+            if entry.object_type != header.object_type {
+                mismatches.push(Diagnostic {
+                    line: None,
+                    header: header.name.clone(),
+                    reason: format!(
+                        "TOC entry {} is a {} but the dump says {}",
+                        entry.dump_id,
+                        entry.object_type.as_str(),
+                        header.object_type.as_str()
+                    ),
+                });
+                continue;
+            }
+            if !entry.owner.is_empty() {
+                header.owner = entry.owner.clone();
             }
+            if entry.schema != "-" {
+                header.schema = entry.schema.clone();
+            }
+        }
+        self.diagnostics.extend(mismatches);
+    }
+
+    pub fn write_to_fs(&self, path: &Path, options: &WriteOptions) -> Result<(), Box<dyn Error>> {
+        // rendered up front so a section we can't place fails before anything is touched
+        let files = self.render(options)?;
+        write_tree(path, &files, options.force)
+    }
+
+    // like write_to_fs but only touches the files that changed
+    pub fn sync_to_fs(
+        &self,
+        path: &Path,
+        options: &WriteOptions,
+    ) -> Result<SyncReport, Box<dyn Error>> {
+        let files = self.render(options)?;
+        sync_tree(path, &files, options.force)
+    }
+
+    // one script that recreates the schema in an empty database, the settings of the prologue
+    // and then every section after the ones it depends on
+    pub fn restore_script(&self) -> Result<String, Box<dyn Error>> {
+        let sections = DependencyGraph::new(self)
+            .order()
+            .map_err(|cycle| cycle.to_string())?;
+        let mut parts: Vec<String> = sections.iter().map(|section| section.render()).collect();
+        if !self.metadata.is_empty() {
+            parts.insert(0, self.metadata.prologue(true).trim_end().to_string());
         }
-        fs::create_dir(path)?;
+        Ok(format!("{}\n", parts.join("\n\n\n")))
+    }
+
+    // the contents of every output file, keyed by its path relative to the output directory.
+    // Each file lists its sections in restore order and then by name, so the same schema renders
+    // to the same bytes whichever order pg_dump wrote it in
+    pub fn render(
+        &self,
+        options: &WriteOptions,
+    ) -> Result<BTreeMap<PathBuf, String>, Box<dyn Error>> {
+        let path = Path::new("");
+        let mut layout = Layout::default();
         for table in &self.tables {
-            let section_path = path.join("tables").join(&table.header.schema);
-            fs::create_dir_all(&section_path)?;
-            let table_name = &table.header.name;
-            let fp = section_path.join(format!("{table_name}.sql"));
-            fs::write(fp, &table.body)?;
+            layout.place(
+                table_path(path, &table.header.schema, &table.header.name),
+                table,
+            );
         }
 
+        // overloads share a file, ordered by their signature
         for function in &self.functions {
-            let section_path = match function.header.name.contains("test_") {
-                true => path.join("tests").join(&function.header.schema),
-                false => path.join("functions").join(&function.header.schema),
-            };
-            fs::create_dir_all(&section_path)?;
-            let function_name = &function.header.name;
-            let fp = section_path.join(format!("{function_name}.sql"));
-            fs::write(fp, &function.body)?;
+            let fp = function_path(path, &function.header.schema, &function.header.name);
+            layout.place(fp, function);
         }
 
+        // a shell type shares its file with the full definition that follows it
         for sql_type in &self.types {
-            let section_path = path.join("types").join(&sql_type.header.schema);
-            fs::create_dir_all(&section_path)?;
-            let type_name = &sql_type.header.name;
-            let fp = section_path.join(format!("{type_name}.sql"));
-            fs::write(fp, &sql_type.body)?;
+            let fp = type_path(path, &sql_type.header.schema, &sql_type.header.name);
+            layout.place(fp, sql_type);
         }
 
+        let mut views = HashSet::new();
+        for view in &self.views {
+            views.insert((view.header.schema.as_str(), unquote(&view.header.name)));
+            let fp = view_path(path, &view.header.schema, &view.header.name);
+            layout.place(fp, view);
+        }
+        // rules and grants are attached to a relation which may be a view or a table
+        let relation_path =
+            |schema: &str, name: &str| match views.contains(&(schema, unquote(name))) {
+                true => view_path(path, schema, name),
+                false => table_path(path, schema, name),
+            };
+
         for constraint in &self.constraints {
-            let table_name = constraint
-                .body
-                .trim_start_matches("\n")
-                .strip_prefix("ALTER TABLE ONLY")
-                .ok_or("Constraint format unknown")?
-                .split("\n")
-                .next()
-                .ok_or("No newline found")?
-                .split(".")
-                .nth(1)
-                .ok_or("Couldn't parse as schema.table")?
-                .trim()
-                .replace('"', "");
-            let section_path = path.join("tables").join(&constraint.header.schema);
-            fs::create_dir_all(&section_path)?;
-            let fp = section_path.join(format!("{table_name}.sql"));
-            let mut file = fs::OpenOptions::new().append(true).create(true).open(fp)?;
-
-            writeln!(file, "{}", constraint.body)?;
+            let table_name = constraint.table_name()?;
+            let fp = table_path(path, &constraint.header.schema, &table_name);
+            layout.place(fp, constraint);
         }
 
+        // partition indexes are attached to the index of their parent, so we look up the table of
+        // the child index
+        let mut index_tables = HashMap::new();
         for index in &self.indexes {
-            let table_name = index
-                .body
-                .trim_start_matches('\n')
-                .split_once(" ON ")
-                .ok_or("no on clause")?
-                .1
-                .split_whitespace()
-                .next()
-                .ok_or("no table name")?
-                .split('.')
-                .nth(1)
-                .ok_or("no table name after schema")?
-                .trim()
-                .replace('"', "");
-            let section_path = path.join("tables").join(&index.header.schema);
-            fs::create_dir_all(&section_path)?;
-            let fp = section_path.join(format!("{table_name}.sql"));
-            let mut file = fs::OpenOptions::new().append(true).create(true).open(fp)?;
-
-            writeln!(file, "{}", index.body)?;
+            if index.header.object_type == ObjectType::IndexAttach {
+                continue;
+            }
+            let table_name = index.table_name()?;
+            let fp = relation_path(&index.header.schema, &table_name);
+            layout.place(fp, index);
+            index_tables.insert(
+                (index.header.schema.as_str(), unquote(&index.header.name)),
+                table_name,
+            );
+        }
+        for index in &self.indexes {
+            if index.header.object_type != ObjectType::IndexAttach {
+                continue;
+            }
+            let fp = match index_tables
+                .get(&(index.header.schema.as_str(), unquote(&index.header.name)))
+            {
+                Some(table_name) => table_path(path, &index.header.schema, table_name),
+                None => path.join("general.sql"),
+            };
+            layout.place(fp, index);
+        }
+
+        // triggers are named `<table> <trigger>`, the table may also be a view with INSTEAD OF
+        // triggers
+        for trigger in &self.triggers {
+            let table_name = trigger.table_name()?;
+            let fp = relation_path(&trigger.header.schema, &table_name);
+            let function = trigger_function(&trigger.body);
+            match (options.link_trigger_functions, function) {
+                (true, Some((schema, function_name))) => {
+                    let schema = schema.unwrap_or(trigger.header.schema.clone());
+                    let function_fp = function_path(path, &schema, &function_name);
+                    layout.note(
+                        function_fp.clone(),
+                        trigger,
+                        format!(
+                            "-- trigger {} on {}.{table_name}, see {}",
+                            trigger_name(&trigger.header.name),
+                            trigger.header.schema,
+                            fp.display()
+                        ),
+                    );
+                    layout.place_with_note(
+                        fp,
+                        trigger,
+                        format!(
+                            "-- executes {schema}.{function_name}, see {}",
+                            function_fp.display()
+                        ),
+                    );
+                }
+                _ => layout.place(fp, trigger),
+            }
+        }
+
+        for policy in &self.policies {
+            let table_name = policy.table_name()?;
+            let fp = policy_path(path, &policy.header.schema, &table_name);
+            layout.place(fp, policy);
+        }
+
+        for rule in &self.rules {
+            let table_name = rule.table_name()?;
+            layout.place(relation_path(&rule.header.schema, &table_name), rule);
+        }
+
+        for object in &self.standalone {
+            let header = &object.header;
+            let fp = standalone_path(path, header.object_type, &header.schema, &header.name);
+            layout.place(fp, object);
+        }
+
+        for data in &self.data {
+            let fp = match data.header.object_type {
+                ObjectType::TableData => path
+                    .join("data")
+                    .join(&data.header.schema)
+                    .join(format!("{}.sql", unquote(&data.header.name))),
+                _ => path.join("data").join("large_objects.sql"),
+            };
+            layout.place(fp, data);
+        }
+
+        // sequences live next to the table that owns them, either through an identity column or
+        // an OWNED BY statement, free standing sequences get their own folder
+        let mut sequence_paths = HashMap::new();
+        for sequence in &self.sequences {
+            if let Some(table_name) = sequence_owner(&sequence.body) {
+                let key = (
+                    sequence.header.schema.as_str(),
+                    unquote(&sequence.header.name),
+                );
+                sequence_paths.insert(key, table_path(path, &sequence.header.schema, &table_name));
+            }
+        }
+        let sequence_path = |schema: &str, name: &str| {
+            let name = unquote(name);
+            match sequence_paths.get(&(schema, name.clone())) {
+                Some(fp) => fp.clone(),
+                None => path
+                    .join("sequences")
+                    .join(schema)
+                    .join(format!("{name}.sql")),
+            }
+        };
+
+        for sequence in &self.sequences {
+            let fp = sequence_path(&sequence.header.schema, &sequence.header.name);
+            layout.place(fp, sequence);
+        }
+
+        // column defaults are named `<table> <column>` and restore after the sequences, so a
+        // nextval default sits right after the sequence it draws from
+        for default in &self.defaults {
+            let table_name = default.table_name()?;
+            layout.place(relation_path(&default.header.schema, &table_name), default);
+        }
+
+        // ACL and COMMENT sections go next to the object they refer to, anything we can't place
+        // (extensions, schemas, ...) goes to general
+        let object_path = |header: &SchemaHeader| {
+            let schema = header.schema.as_str();
+            match header.target() {
+                // views are granted to as `TABLE <view>`
+                Some(("TABLE" | "VIEW" | "MATERIALIZED VIEW", name)) => relation_path(schema, name),
+                Some(("FOREIGN TABLE", name)) => table_path(path, schema, name),
+                Some(("COLUMN", name)) => {
+                    let table_name = name.split_once('.').map_or(name, |(table, _)| table);
+                    relation_path(schema, table_name)
+                }
+                // e.g. `CONSTRAINT users_pkey ON users`
+                Some(("CONSTRAINT", name)) => match name.split_once(" ON ") {
+                    Some((_, table_name)) => table_path(path, schema, table_name),
+                    None => path.join("general.sql"),
+                },
+                Some(("TRIGGER", name)) => match name.split_once(" ON ") {
+                    Some((_, table_name)) => relation_path(schema, table_name),
+                    None => path.join("general.sql"),
+                },
+                Some(("POLICY", name)) => match name.split_once(" ON ") {
+                    Some((_, table_name)) => policy_path(path, schema, table_name),
+                    None => path.join("general.sql"),
+                },
+                Some(("RULE", name)) => match name.split_once(" ON ") {
+                    Some((_, table_name)) => relation_path(schema, table_name),
+                    None => path.join("general.sql"),
+                },
+                Some(("INDEX", name)) => match index_tables.get(&(schema, unquote(name))) {
+                    Some(table_name) => relation_path(schema, table_name),
+                    None => path.join("general.sql"),
+                },
+                Some(("FUNCTION" | "PROCEDURE" | "AGGREGATE", name)) => {
+                    function_path(path, schema, name)
+                }
+                Some(("SEQUENCE", name)) => sequence_path(schema, name),
+                Some(("TYPE" | "DOMAIN", name)) => type_path(path, schema, name),
+                Some(("FOREIGN SERVER", name)) => {
+                    standalone_path(path, ObjectType::Server, schema, name)
+                }
+                Some((kind, name)) => match kind.parse::<ObjectType>() {
+                    Ok(object_type) => standalone_path(path, object_type, schema, name),
+                    Err(_) => path.join("general.sql"),
+                },
+                None => path.join("general.sql"),
+            }
+        };
+
+        for comment in &self.comments {
+            layout.place(object_path(&comment.header), comment);
+        }
+
+        for acl in &self.acls {
+            layout.place(object_path(&acl.header), acl);
         }
 
         for setup_snippet in &self.general {
-            let mut file = fs::OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(path.join("general.sql"))?;
-            writeln!(file, "{}", setup_snippet.body)?;
+            layout.place(path.join("general.sql"), setup_snippet);
         }
-        Ok(())
+
+        let mut files = layout.render();
+        if !self.metadata.is_empty() {
+            files.insert(
+                path.join("00_prologue.sql"),
+                self.metadata.prologue(options.strip_volatile),
+            );
+        }
+        Ok(files)
+    }
+}
+
+// the sections of every output file, kept apart until they are all known so each file can be
+// sorted as a whole
+#[derive(Default)]
+struct Layout<'a> {
+    files: HashMap<PathBuf, Vec<Entry<'a>>>,
+}
+
+struct Entry<'a> {
+    rank: u8,
+    name: &'a str,
+    signature: Option<&'a str>,
+    // default privileges share their name, the schema and role tell them apart
+    schema: &'a str,
+    owner: &'a str,
+    text: String,
+}
+
+impl<'a> Layout<'a> {
+    fn place(&mut self, fp: PathBuf, section: &'a SchemaSection) {
+        self.push(fp, section, section.render());
+    }
+
+    // a comment of ours above the statements of a section
+    fn place_with_note(&mut self, fp: PathBuf, section: &'a SchemaSection, note: String) {
+        let text = format!(
+            "{}\n\n{note}\n{}",
+            section.header,
+            section.body.trim_matches('\n')
+        );
+        self.push(fp, section, text);
+    }
+
+    // a comment of ours that stands on its own, sorted as if it were the section
+    fn note(&mut self, fp: PathBuf, section: &'a SchemaSection, note: String) {
+        self.push(fp, section, note);
+    }
+
+    fn push(&mut self, fp: PathBuf, section: &'a SchemaSection, text: String) {
+        self.files.entry(fp).or_default().push(Entry {
+            rank: section.header.object_type.restore_rank(),
+            name: &section.header.name,
+            signature: section.header.signature.as_deref(),
+            schema: &section.header.schema,
+            owner: &section.header.owner,
+            text,
+        });
+    }
+
+    // sections are separated by two blank lines, the same as the statements within a section
+    fn render(self) -> BTreeMap<PathBuf, String> {
+        self.files
+            .into_iter()
+            .map(|(fp, mut entries)| {
+                entries.sort_by_key(|entry| {
+                    (
+                        entry.rank,
+                        entry.name,
+                        entry.signature,
+                        entry.schema,
+                        entry.owner,
+                    )
+                });
+                let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_str()).collect();
+                (fp, format!("{}\n", texts.join("\n\n\n")))
+            })
+            .collect()
+    }
+}
+
+fn table_path(path: &Path, schema: &str, table_name: &str) -> PathBuf {
+    path.join("tables")
+        .join(schema)
+        .join(format!("{}.sql", unquote(table_name)))
+}
+
+fn view_path(path: &Path, schema: &str, view_name: &str) -> PathBuf {
+    path.join("views")
+        .join(schema)
+        .join(format!("{}.sql", unquote(view_name)))
+}
+
+fn type_path(path: &Path, schema: &str, type_name: &str) -> PathBuf {
+    path.join("types")
+        .join(schema)
+        .join(format!("{}.sql", unquote(type_name)))
+}
+
+// all the policies of a table, along with its ROW SECURITY switch, share a file
+fn policy_path(path: &Path, schema: &str, table_name: &str) -> PathBuf {
+    path.join("policies")
+        .join(schema)
+        .join(format!("{}.sql", unquote(table_name)))
+}
+
+// objects that aren't attached to a table or function get a top level folder per kind, anything
+// that isn't worth a folder of its own goes to general
+fn standalone_path(path: &Path, object_type: ObjectType, schema: &str, name: &str) -> PathBuf {
+    // publication and subscription members are named `<publication> <table>`
+    let owner = unquote(name.split_whitespace().next().unwrap_or(name));
+    match object_type {
+        ObjectType::Collation => path
+            .join("collations")
+            .join(schema)
+            .join(format!("{}.sql", unquote(name))),
+        ObjectType::TextSearchConfiguration
+        | ObjectType::TextSearchDictionary
+        | ObjectType::TextSearchParser
+        | ObjectType::TextSearchTemplate => path
+            .join("text_search")
+            .join(schema)
+            .join(format!("{}.sql", unquote(name))),
+        ObjectType::EventTrigger => path
+            .join("event_triggers")
+            .join(format!("{}.sql", unquote(name))),
+        ObjectType::Server => path.join("servers").join(format!("{}.sql", unquote(name))),
+        // e.g. `USER MAPPING postgres SERVER remote`
+        ObjectType::UserMapping => match name.rsplit_once(" SERVER ") {
+            Some((_, server)) => path
+                .join("servers")
+                .join(format!("{}.sql", unquote(server))),
+            None => path.join("general.sql"),
+        },
+        ObjectType::Publication
+        | ObjectType::PublicationTable
+        | ObjectType::PublicationTablesInSchema => {
+            path.join("publications").join(format!("{owner}.sql"))
+        }
+        ObjectType::Subscription | ObjectType::SubscriptionTable => {
+            path.join("subscriptions").join(format!("{owner}.sql"))
+        }
+        _ => path.join("general.sql"),
+    }
+}
+
+fn function_path(path: &Path, schema: &str, name: &str) -> PathBuf {
+    let section_path = match name.contains("test_") {
+        true => path.join("tests").join(schema),
+        false => path.join("functions").join(schema),
+    };
+    section_path.join(format!("{name}.sql"))
+}
+
+// ALTER TABLE ONLY dirac.brand
+//     ADD CONSTRAINT brand_pkey PRIMARY KEY (id);
+fn constraint_table(body: &str) -> Result<String, Box<dyn Error>> {
+    Ok(body
+        .trim_start_matches("\n")
+        .strip_prefix("ALTER TABLE")
+        .ok_or("Constraint format unknown")?
+        .trim_start_matches(" ONLY")
+        .split("\n")
+        .next()
+        .ok_or("No newline found")?
+        .split(".")
+        .nth(1)
+        .ok_or("Couldn't parse as schema.table")?
+        .trim()
+        .replace('"', ""))
+}
+
+// CREATE INDEX idx_users_email ON outbound.users USING btree (email);
+// CREATE INDEX measure_at_idx ON ONLY shop.measure USING btree (at);
+fn index_table(body: &str) -> Result<String, Box<dyn Error>> {
+    Ok(body
+        .trim_start_matches('\n')
+        .split_once(" ON ")
+        .ok_or("no on clause")?
+        .1
+        .trim_start_matches("ONLY ")
+        .split_whitespace()
+        .next()
+        .ok_or("no table name")?
+        .split('.')
+        .nth(1)
+        .ok_or("no table name after schema")?
+        .trim()
+        .replace('"', ""))
+}
+
+// the function a trigger runs, with its schema when it is qualified
+// CREATE TRIGGER brand_slug BEFORE INSERT ON dirac.brand FOR EACH ROW EXECUTE FUNCTION dirac.slugify();
+fn trigger_function(body: &str) -> Option<(Option<String>, String)> {
+    let (_, call) = body
+        .split_once(" EXECUTE FUNCTION ")
+        .or_else(|| body.split_once(" EXECUTE PROCEDURE "))?;
+    let (qualified, _) = call.split_once('(')?;
+    match qualified.trim().split_once('.') {
+        Some((schema, name)) => Some((Some(unquote(schema)), unquote(name))),
+        None => Some((None, unquote(qualified))),
+    }
+}
+
+// -- executes dirac.slugify, see functions/dirac/slugify.sql
+// -- trigger brand_slug on dirac.brand, see tables/dirac/brand.sql
+pub fn is_link_note(line: &str) -> bool {
+    (line.starts_with("-- executes ") || line.starts_with("-- trigger "))
+        && line.contains(", see ")
+        && line.ends_with(".sql")
+}
+
+// `brand generate_brand_slug_trigger` to `generate_brand_slug_trigger`
+fn trigger_name(name: &str) -> &str {
+    name.split_once(' ').map_or(name, |(_, trigger)| trigger)
+}
+
+fn unquote(name: &str) -> String {
+    name.trim().replace('"', "")
+}
+
+// finds the table a sequence belongs to, from either of:
+// ALTER TABLE dirac.customer ALTER COLUMN id ADD GENERATED ALWAYS AS IDENTITY (
+// ALTER SEQUENCE outbound.users_id_seq OWNED BY outbound.users.id;
+fn sequence_owner(body: &str) -> Option<String> {
+    let body = body.trim_start_matches('\n');
+    if let Some(rest) = body.strip_prefix("ALTER TABLE ")
+        && body.contains(" ADD GENERATED ")
+    {
+        let qualified = rest.trim_start_matches("ONLY ").split_whitespace().next()?;
+        return Some(unquote(qualified.split('.').nth(1)?));
+    }
+    let (_, owned_by) = body.split_once(" OWNED BY ")?;
+    let column = owned_by.split(';').next()?.trim();
+    if column == "NONE" {
+        return None;
+    }
+    Some(unquote(column.split('.').nth(1)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(schema: &Schema) -> BTreeMap<PathBuf, String> {
+        schema.render(&WriteOptions::default()).unwrap()
+    }
+
+    // the headers of the sections in a file, in the order they were written
+    fn names(file: &str) -> Vec<&str> {
+        file.lines()
+            .filter_map(|line| line.strip_prefix("-- Name: "))
+            .collect()
+    }
+
+    #[test]
+    fn acls_and_sequences_sit_with_their_object() {
+        let schema = Schema::from_sections(&[
+            (
+                "customer",
+                "TABLE",
+                "CREATE TABLE shop.customer (\n    id integer NOT NULL\n);",
+            ),
+            (
+                "customer_id_seq",
+                "SEQUENCE",
+                "CREATE SEQUENCE shop.customer_id_seq\n    AS integer;",
+            ),
+            (
+                "customer_id_seq",
+                "SEQUENCE OWNED BY",
+                "ALTER SEQUENCE shop.customer_id_seq OWNED BY shop.customer.id;",
+            ),
+            (
+                "invoice_no",
+                "SEQUENCE",
+                "CREATE SEQUENCE shop.invoice_no\n    START WITH 1000;",
+            ),
+            (
+                "TABLE customer",
+                "ACL",
+                "GRANT SELECT ON TABLE shop.customer TO api;",
+            ),
+            (
+                "SEQUENCE invoice_no",
+                "ACL",
+                "GRANT USAGE ON SEQUENCE shop.invoice_no TO api;",
+            ),
+            (
+                "FUNCTION total(a integer)",
+                "ACL",
+                "REVOKE ALL ON FUNCTION shop.total(a integer) FROM PUBLIC;",
+            ),
+            ("SCHEMA shop", "ACL", "GRANT USAGE ON SCHEMA shop TO api;"),
+        ]);
+        let files = render(&schema);
+        assert_eq!(
+            names(&files[Path::new("tables/shop/customer.sql")]),
+            [
+                "customer; Type: TABLE; Schema: shop; Owner: postgres",
+                "customer_id_seq; Type: SEQUENCE; Schema: shop; Owner: postgres",
+                "customer_id_seq; Type: SEQUENCE OWNED BY; Schema: shop; Owner: postgres",
+                "TABLE customer; Type: ACL; Schema: shop; Owner: postgres",
+            ]
+        );
+        assert_eq!(
+            names(&files[Path::new("sequences/shop/invoice_no.sql")]),
+            [
+                "invoice_no; Type: SEQUENCE; Schema: shop; Owner: postgres",
+                "SEQUENCE invoice_no; Type: ACL; Schema: shop; Owner: postgres",
+            ]
+        );
+        assert!(files[Path::new("functions/shop/total.sql")].contains("REVOKE ALL ON FUNCTION"));
+        assert!(files[Path::new("general.sql")].contains("GRANT USAGE ON SCHEMA shop TO api;"));
+    }
+
+    #[test]
+    fn comments_sit_with_their_object() {
+        let schema = Schema::from_sections(&[
+            (
+                "order",
+                "TABLE",
+                "CREATE TABLE shop.\"order\" (\n    paid_at timestamp\n);",
+            ),
+            (
+                "TABLE \"order\"",
+                "COMMENT",
+                "COMMENT ON TABLE shop.\"order\" IS 'what was bought';",
+            ),
+            (
+                "COLUMN \"order\".paid_at",
+                "COMMENT",
+                "COMMENT ON COLUMN shop.\"order\".paid_at IS 'null until paid';",
+            ),
+            (
+                "FUNCTION total(a integer)",
+                "COMMENT",
+                "COMMENT ON FUNCTION shop.total(a integer) IS 'sum of the lines';",
+            ),
+            (
+                "TYPE status",
+                "COMMENT",
+                "COMMENT ON TYPE shop.status IS 'where an order is';",
+            ),
+            (
+                "EXTENSION citext",
+                "COMMENT",
+                "COMMENT ON EXTENSION citext IS 'case insensitive text';",
+            ),
+        ]);
+        let files = render(&schema);
+        assert_eq!(
+            names(&files[Path::new("tables/shop/order.sql")]),
+            [
+                "order; Type: TABLE; Schema: shop; Owner: postgres",
+                "COLUMN \"order\".paid_at; Type: COMMENT; Schema: shop; Owner: postgres",
+                "TABLE \"order\"; Type: COMMENT; Schema: shop; Owner: postgres",
+            ]
+        );
+        assert!(files[Path::new("functions/shop/total.sql")].contains("sum of the lines"));
+        assert!(files[Path::new("types/shop/status.sql")].contains("where an order is"));
+        assert!(files[Path::new("general.sql")].contains("case insensitive text"));
+    }
+
+    #[test]
+    fn views_policies_and_publications_get_their_own_files() {
+        let schema = Schema::from_sections(&[
+            (
+                "big_orders",
+                "VIEW",
+                "CREATE VIEW shop.big_orders AS\n SELECT 1 AS id;",
+            ),
+            (
+                "TABLE big_orders",
+                "ACL",
+                "GRANT SELECT ON TABLE shop.big_orders TO api;",
+            ),
+            (
+                "big_orders keep",
+                "RULE",
+                "CREATE RULE keep AS\n    ON DELETE TO shop.big_orders DO INSTEAD NOTHING;",
+            ),
+            (
+                "order",
+                "ROW SECURITY",
+                "ALTER TABLE shop.\"order\" ENABLE ROW LEVEL SECURITY;",
+            ),
+            (
+                "order mine",
+                "POLICY",
+                "CREATE POLICY mine ON shop.\"order\" USING (true);",
+            ),
+            ("orders", "PUBLICATION", "CREATE PUBLICATION orders;"),
+            (
+                "orders order",
+                "PUBLICATION TABLE",
+                "ALTER PUBLICATION orders ADD TABLE ONLY shop.\"order\";",
+            ),
+        ]);
+        let files = render(&schema);
+        let paths: Vec<_> = files.keys().map(|fp| fp.to_str().unwrap()).collect();
+        assert_eq!(
+            paths,
+            [
+                "policies/shop/order.sql",
+                "publications/orders.sql",
+                "views/shop/big_orders.sql"
+            ]
+        );
+        assert_eq!(
+            names(&files[Path::new("views/shop/big_orders.sql")]),
+            [
+                "big_orders; Type: VIEW; Schema: shop; Owner: postgres",
+                "big_orders keep; Type: RULE; Schema: shop; Owner: postgres",
+                "TABLE big_orders; Type: ACL; Schema: shop; Owner: postgres",
+            ]
+        );
+        assert_eq!(
+            names(&files[Path::new("policies/shop/order.sql")]),
+            [
+                "order; Type: ROW SECURITY; Schema: shop; Owner: postgres",
+                "order mine; Type: POLICY; Schema: shop; Owner: postgres",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_sections_are_reported_with_their_line() {
+        let dump = "--\n-- PostgreSQL database dump\n--\n\n\
+            --\n-- Name: customer; Type: TABLE; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE TABLE shop.customer (\n    id integer\n);\n\n\n\
+            --\n-- Name: gizmo; Type: WIDGET; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE WIDGET shop.gizmo;\n\n\n\
+            --\n-- Name: order; Type: TABLE; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE TABLE shop.\"order\" (\n    id integer\n);\n";
+        let schema = dump.parse::<Schema>().unwrap();
+        let [diagnostic] = schema.diagnostics() else {
+            panic!("expected one diagnostic: {:?}", schema.diagnostics());
+        };
+        let line = dump
+            .lines()
+            .position(|line| line.contains("gizmo; Type: WIDGET"))
+            .unwrap()
+            + 1;
+        assert_eq!(diagnostic.line, Some(line));
+        assert_eq!(
+            diagnostic.to_string(),
+            format!(
+                "line {line}: skipped `-- Name: gizmo; Type: WIDGET; Schema: shop; Owner: \
+                 postgres`: Unknown object type: WIDGET"
+            )
+        );
+        // the body of the skipped section isn't taken for a header, the next table still parses
+        let files = render(&schema);
+        assert!(files.contains_key(Path::new("tables/shop/order.sql")));
+        assert!(!files.values().any(|file| file.contains("WIDGET")));
+
+        assert!(crate::report_diagnostics(&schema, false).is_ok());
+        assert_eq!(
+            crate::report_diagnostics(&schema, true)
+                .unwrap_err()
+                .to_string(),
+            "1 sections could not be classified"
+        );
+    }
+
+    #[test]
+    fn overloads_share_a_file_and_keep_their_signatures() {
+        let schema = Schema::from_sections(&[
+            (
+                "total(a text)",
+                "FUNCTION",
+                "CREATE FUNCTION shop.total(a text) RETURNS integer\n    LANGUAGE sql\n    \
+                 AS $$ SELECT length(a) $$;",
+            ),
+            (
+                "total(a integer)",
+                "FUNCTION",
+                "CREATE FUNCTION shop.total(a integer) RETURNS integer\n    LANGUAGE sql\n    \
+                 AS $$ SELECT a $$;",
+            ),
+        ]);
+        let headers: Vec<_> = schema
+            .sections()
+            .map(|section| {
+                (
+                    section.header.name.as_str(),
+                    section.header.signature.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            headers,
+            [("total", Some("(a text)")), ("total", Some("(a integer)"))]
+        );
+        let files = render(&schema);
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            names(&files[Path::new("functions/shop/total.sql")]),
+            [
+                "total(a integer); Type: FUNCTION; Schema: shop; Owner: postgres",
+                "total(a text); Type: FUNCTION; Schema: shop; Owner: postgres",
+            ]
+        );
+    }
+
+    #[test]
+    fn triggers_sit_with_their_table() {
+        let schema = Schema::from_sections(&[
+            (
+                "order",
+                "TABLE",
+                "CREATE TABLE shop.\"order\" (\n    updated_at timestamp\n);",
+            ),
+            (
+                "stamp()",
+                "FUNCTION",
+                "CREATE FUNCTION shop.stamp() RETURNS trigger\n    LANGUAGE plpgsql\n    \
+                 AS $$ BEGIN RETURN NEW; END $$;",
+            ),
+            (
+                "order order_stamp",
+                "TRIGGER",
+                "CREATE TRIGGER order_stamp BEFORE UPDATE ON shop.\"order\" FOR EACH ROW \
+                 EXECUTE FUNCTION shop.stamp();",
+            ),
+            (
+                "recent",
+                "VIEW",
+                "CREATE VIEW shop.recent AS\n SELECT 1 AS id;",
+            ),
+            (
+                "recent recent_insert",
+                "TRIGGER",
+                "CREATE TRIGGER recent_insert INSTEAD OF INSERT ON shop.recent FOR EACH ROW \
+                 EXECUTE FUNCTION shop.stamp();",
+            ),
+        ]);
+        let files = render(&schema);
+        assert!(files[Path::new("tables/shop/order.sql")].contains("CREATE TRIGGER order_stamp"));
+        assert!(files[Path::new("views/shop/recent.sql")].contains("CREATE TRIGGER recent_insert"));
+        assert!(!files[Path::new("functions/shop/stamp.sql")].contains("TRIGGER"));
+
+        let options = WriteOptions {
+            link_trigger_functions: true,
+            ..WriteOptions::default()
+        };
+        let files = schema.render(&options).unwrap();
+        assert!(
+            files[Path::new("tables/shop/order.sql")]
+                .contains("-- executes shop.stamp, see functions/shop/stamp.sql\nCREATE TRIGGER")
+        );
+        assert!(files[Path::new("functions/shop/stamp.sql")].contains(
+            "-- trigger order_stamp on shop.order, see tables/shop/order.sql\n\n\n\
+                 -- trigger recent_insert on shop.recent, see views/shop/recent.sql\n"
+        ));
+    }
+
+    #[test]
+    fn defaults_follow_the_sequence_in_the_table_file() {
+        let schema = Schema::from_sections(&[
+            (
+                "customer customer_pkey",
+                "CONSTRAINT",
+                "ALTER TABLE ONLY shop.customer\n    ADD CONSTRAINT customer_pkey PRIMARY KEY (id);",
+            ),
+            (
+                "customer id",
+                "DEFAULT",
+                "ALTER TABLE ONLY shop.customer ALTER COLUMN id SET DEFAULT \
+                 nextval('shop.customer_id_seq'::regclass);",
+            ),
+            (
+                "customer_id_seq",
+                "SEQUENCE OWNED BY",
+                "ALTER SEQUENCE shop.customer_id_seq OWNED BY shop.customer.id;",
+            ),
+            (
+                "customer_id_seq",
+                "SEQUENCE",
+                "CREATE SEQUENCE shop.customer_id_seq\n    AS integer;",
+            ),
+            (
+                "customer",
+                "TABLE",
+                "CREATE TABLE shop.customer (\n    id integer NOT NULL\n);",
+            ),
+            (
+                "recent",
+                "VIEW",
+                "CREATE VIEW shop.recent AS\n SELECT 1 AS id;",
+            ),
+            (
+                "recent id",
+                "DEFAULT",
+                "ALTER TABLE ONLY shop.recent ALTER COLUMN id SET DEFAULT 0;",
+            ),
+        ]);
+        let files = render(&schema);
+        assert!(!files.contains_key(Path::new("general.sql")));
+        assert_eq!(
+            names(&files[Path::new("tables/shop/customer.sql")]),
+            [
+                "customer; Type: TABLE; Schema: shop; Owner: postgres",
+                "customer_id_seq; Type: SEQUENCE; Schema: shop; Owner: postgres",
+                "customer_id_seq; Type: SEQUENCE OWNED BY; Schema: shop; Owner: postgres",
+                "customer id; Type: DEFAULT; Schema: shop; Owner: postgres",
+                "customer customer_pkey; Type: CONSTRAINT; Schema: shop; Owner: postgres",
+            ]
+        );
+        assert!(files[Path::new("views/shop/recent.sql")].contains("SET DEFAULT 0;"));
+    }
+
+    #[test]
+    fn preamble_becomes_the_prologue() {
+        let dump = "--\n-- PostgreSQL database dump\n--\n\n\
+            \\restrict 3kM9vQ2x\n\n\
+            -- Dumped from database version 17.6 (Debian 17.6-2.pgdg12+1)\n\
+            -- Dumped by pg_dump version 17.6 (Debian 17.6-2.pgdg12+1)\n\n\
+            SET statement_timeout = 0;\n\
+            SET client_encoding = 'UTF8';\n\
+            SELECT pg_catalog.set_config('search_path', '', false);\n\
+            SET check_function_bodies = false;\n\n\
+            --\n-- Name: shop; Type: SCHEMA; Schema: -; Owner: postgres\n--\n\n\
+            CREATE SCHEMA shop;\n\n\n\
+            SET default_tablespace = '';\n\n\
+            SET default_table_access_method = heap;\n\n\
+            --\n-- Name: customer; Type: TABLE; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE TABLE shop.customer (\n    id integer\n);\n\n\n\
+            SET default_tablespace = fast;\n\n\
+            --\n-- Name: customer_id; Type: INDEX; Schema: shop; Owner: postgres\n--\n\n\
+            CREATE INDEX customer_id ON shop.customer USING btree (id);\n\n\n\
+            --\n-- PostgreSQL database dump complete\n--\n\n\
+            \\unrestrict 3kM9vQ2x\n";
+        let schema = dump.parse::<Schema>().unwrap();
+        let metadata = &schema.metadata;
+        let version = PgVersion {
+            major: 17,
+            minor: 6,
+            patch: None,
+            build: Some("(Debian 17.6-2.pgdg12+1)".to_string()),
+        };
+        assert_eq!(metadata.server_version, Some(version.clone()));
+        assert_eq!(metadata.client_version, Some(version));
+        assert_eq!(metadata.search_path.as_deref(), Some(""));
+        assert_eq!(
+            metadata.settings,
+            [
+                ("statement_timeout", "0"),
+                ("client_encoding", "'UTF8'"),
+                ("check_function_bodies", "false"),
+                ("default_tablespace", "''"),
+                ("default_table_access_method", "heap"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+
+        let files = render(&schema);
+        assert_eq!(
+            files[Path::new("00_prologue.sql")],
+            "-- Dumped from database version 17.6 (Debian 17.6-2.pgdg12+1)\n\
+             -- Dumped by pg_dump version 17.6 (Debian 17.6-2.pgdg12+1)\n\n\
+             SET statement_timeout = 0;\n\
+             SET client_encoding = 'UTF8';\n\
+             SET check_function_bodies = false;\n\
+             SET default_tablespace = '';\n\
+             SET default_table_access_method = heap;\n\
+             SELECT pg_catalog.set_config('search_path', '', false);\n"
+        );
+        assert!(!files.values().any(|file| file.contains("restrict")));
+        // a setting that differs from the prologue's is for the next object and stays with it
+        let customer = &files[Path::new("tables/shop/customer.sql")];
+        assert!(customer.contains("SET default_tablespace = fast;"));
+        assert!(!customer.contains("SET default_table_access_method"));
+
+        let options = WriteOptions {
+            strip_volatile: true,
+            ..WriteOptions::default()
+        };
+        let prologue = &schema.render(&options).unwrap()[Path::new("00_prologue.sql")];
+        assert!(prologue.starts_with("SET statement_timeout = 0;\n"));
+    }
+
+    #[test]
+    fn versions_before_10_have_a_patch_number() {
+        let version = "9.6.24".parse::<PgVersion>().unwrap();
+        assert_eq!(
+            (version.major, version.minor, version.patch),
+            (9, 6, Some(24))
+        );
+        assert_eq!(version.to_string(), "9.6.24");
+        assert!("seventeen".parse::<PgVersion>().is_err());
+    }
+
+    #[test]
+    fn render_order_does_not_depend_on_dump_order() {
+        let sections = [
+            (
+                "customer customer_email_key",
+                "CONSTRAINT",
+                "ALTER TABLE ONLY shop.customer\n    ADD CONSTRAINT customer_email_key UNIQUE (email);",
+            ),
+            (
+                "customer customer_pkey",
+                "CONSTRAINT",
+                "ALTER TABLE ONLY shop.customer\n    ADD CONSTRAINT customer_pkey PRIMARY KEY (id);",
+            ),
+            (
+                "TABLE customer",
+                "ACL",
+                "GRANT SELECT ON TABLE shop.customer TO api;",
+            ),
+            (
+                "customer",
+                "TABLE",
+                "CREATE TABLE shop.customer (\n    id integer,\n    email text\n);",
+            ),
+            (
+                "customer_by_email",
+                "INDEX",
+                "CREATE INDEX customer_by_email ON shop.customer USING btree (email);",
+            ),
+        ];
+        let mut reversed = sections;
+        reversed.reverse();
+        let files = render(&Schema::from_sections(&sections));
+        assert_eq!(files, render(&Schema::from_sections(&reversed)));
+        let customer = &files[Path::new("tables/shop/customer.sql")];
+        assert_eq!(
+            names(customer),
+            [
+                "customer; Type: TABLE; Schema: shop; Owner: postgres",
+                "customer customer_email_key; Type: CONSTRAINT; Schema: shop; Owner: postgres",
+                "customer customer_pkey; Type: CONSTRAINT; Schema: shop; Owner: postgres",
+                "customer_by_email; Type: INDEX; Schema: shop; Owner: postgres",
+                "TABLE customer; Type: ACL; Schema: shop; Owner: postgres",
+            ]
+        );
+        // two blank lines between sections and a single newline at the end
+        assert!(customer.contains("id integer,\n    email text\n);\n\n\n--\n"));
+        assert!(customer.ends_with("TO api;\n"));
+    }
+
+    #[test]
+    fn default_privileges_are_ordered_by_schema_and_role() {
+        let section = |schema: &str, owner: &str| {
+            format!(
+                "--\n-- Name: DEFAULT PRIVILEGES FOR TABLES; Type: DEFAULT ACL; Schema: {schema}; \
+                 Owner: {owner}\n--\n\n\
+                 ALTER DEFAULT PRIVILEGES FOR ROLE {owner} GRANT SELECT ON TABLES  TO api;\n\n\n"
+            )
+        };
+        let dump = [
+            "--\n-- PostgreSQL database dump\n--\n\n".to_string(),
+            section("shop", "postgres"),
+            section("-", "postgres"),
+            section("-", "api"),
+        ]
+        .concat();
+        let files = render(&dump.parse::<Schema>().unwrap());
+        // global entries, in no schema, come before those for a schema
+        assert_eq!(
+            names(&files[Path::new("general.sql")]),
+            [
+                "DEFAULT PRIVILEGES FOR TABLES; Type: DEFAULT ACL; Schema: -; Owner: api",
+                "DEFAULT PRIVILEGES FOR TABLES; Type: DEFAULT ACL; Schema: -; Owner: postgres",
+                "DEFAULT PRIVILEGES FOR TABLES; Type: DEFAULT ACL; Schema: shop; Owner: postgres",
+            ]
+        );
     }
 }